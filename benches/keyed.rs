@@ -0,0 +1,56 @@
+//! Compares keyed limiter throughput under contention: a `KeyedFence`
+//! behind a single `Mutex` against a `ShardedKeyedFence`.
+//!
+//! Run with `cargo bench --bench keyed`.
+
+extern crate fence;
+
+use std::{thread, time};
+use std::sync::{Arc, Mutex};
+
+use fence::{KeyedFence, ShardedKeyedFence};
+
+const OPS_PER_THREAD: u32 = 200_000;
+const KEYS: u32 = 10_000;
+
+fn run<F>(threads: u32, f: F) -> f64
+    where F: Fn(u32) + Send + Sync + 'static
+{
+    let f = Arc::new(f);
+    let start = time::Instant::now();
+    let workers: Vec<_> = (0..threads).map(|t| {
+        let f = f.clone();
+        thread::spawn(move || {
+            for i in 0..OPS_PER_THREAD {
+                f(i.wrapping_mul(2_654_435_761).wrapping_add(t) % KEYS);
+            }
+        })
+    }).collect();
+    for w in workers {
+        w.join().unwrap();
+    }
+    let ops = (threads * OPS_PER_THREAD) as f64;
+    ops / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let period = time::Duration::from_millis(1);
+    let max_threads = thread::available_parallelism().map_or(4, |n| n.get() as u32).max(4);
+
+    println!("{:>8} {:>16} {:>16}", "threads", "mutex ops/s", "sharded ops/s");
+    let mut threads = 1;
+    while threads <= max_threads * 2 {
+        let single = Arc::new(Mutex::new(KeyedFence::new(period)));
+        let mutex = run(threads, move |key| {
+            single.lock().unwrap().allow(&key);
+        });
+
+        let sharded = Arc::new(ShardedKeyedFence::new(period));
+        let shards = run(threads, move |key| {
+            sharded.allow(&key);
+        });
+
+        println!("{:>8} {:>16.0} {:>16.0}", threads, mutex, shards);
+        threads *= 2;
+    }
+}
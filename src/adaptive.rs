@@ -0,0 +1,225 @@
+use std::time;
+
+use Fence;
+use clock::{Clock, MonotonicClock};
+use limiter::RateLimiter;
+
+/// Tuning for an `AdaptiveFence`.
+///
+/// Rates are in events per second. `min_rate` must be positive and no
+/// greater than `max_rate`, `increase` must be finite and non-negative, and
+/// `decrease` must lie in (0, 1].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aimd {
+    /// The slowest rate the fence will back off to.
+    pub min_rate: f64,
+    /// The fastest rate the fence will climb to.
+    pub max_rate: f64,
+    /// Events per second added to the rate on each reported success.
+    pub increase: f64,
+    /// Factor applied to the rate on each reported overload, between zero
+    /// and one.
+    pub decrease: f64,
+}
+
+impl Aimd {
+
+    fn validate(&self) {
+        assert!(self.min_rate > 0.0 && time::Duration::try_from_secs_f64(1.0 / self.min_rate).is_ok(),
+                "Aimd::min_rate must be positive, got {}", self.min_rate);
+        assert!(self.max_rate >= self.min_rate,
+                "Aimd::max_rate must be at least min_rate, got {} < {}", self.max_rate, self.min_rate);
+        assert!(self.increase >= 0.0 && self.increase.is_finite(),
+                "Aimd::increase must be finite and non-negative, got {}", self.increase);
+        assert!(self.decrease > 0.0 && self.decrease <= 1.0,
+                "Aimd::decrease must be in (0, 1], got {}", self.decrease);
+    }
+}
+
+/// A fence whose rate follows feedback from the work it guards.
+///
+/// Callers report each outcome with `success` or `overloaded`. Successes
+/// raise the rate additively and overloads cut it multiplicatively, so the
+/// fence backs off quickly when a dependency struggles and probes upward
+/// gently once it recovers, always staying within the configured bounds.
+pub struct AdaptiveFence<C = MonotonicClock> {
+    fence: Fence<C>,
+    config: Aimd,
+    rate: f64,
+}
+
+impl AdaptiveFence {
+
+    /// Construct an adaptive fence starting at `rate` events per second.
+    ///
+    /// Panics if `config` is invalid.
+    pub fn new(config: Aimd, rate: f64) -> AdaptiveFence {
+        AdaptiveFence::with_clock(config, rate, MonotonicClock)
+    }
+}
+
+impl<C: Clock> AdaptiveFence<C> {
+
+    /// Construct an adaptive fence as with `new`, reading time from `clock`.
+    pub fn with_clock(config: Aimd, rate: f64, clock: C) -> AdaptiveFence<C> {
+        config.validate();
+        let rate = clamp(rate, &config);
+        AdaptiveFence {
+            fence: Fence::with_clock(period(rate), clock),
+            config,
+            rate,
+        }
+    }
+
+    /// The current rate in events per second.
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// The current period between events.
+    pub fn period(&self) -> time::Duration {
+        self.fence.duration()
+    }
+
+    /// Report that guarded work succeeded, raising the rate.
+    pub fn success(&mut self) {
+        let rate = self.rate + self.config.increase;
+        self.set_rate(rate);
+    }
+
+    /// Report that guarded work was rejected for overload, cutting the rate.
+    pub fn overloaded(&mut self) {
+        let rate = self.rate * self.config.decrease;
+        self.set_rate(rate);
+    }
+
+    /// Sleep the current thread until the fence opens at the current rate.
+    pub fn sleep(&mut self) {
+        self.fence.sleep()
+    }
+
+    /// Return true and consume the slot if the fence is open at the current
+    /// rate.
+    pub fn allow(&mut self) -> bool {
+        self.fence.allow()
+    }
+
+    fn set_rate(&mut self, rate: f64) {
+        self.rate = clamp(rate, &self.config);
+        self.fence.set_duration(period(self.rate));
+    }
+}
+
+impl<C: Clock> RateLimiter for AdaptiveFence<C> {
+    fn try_acquire(&mut self) -> bool {
+        self.fence.try_acquire()
+    }
+
+    fn acquire(&mut self) {
+        self.fence.acquire()
+    }
+
+    fn time_until_ready(&self) -> time::Duration {
+        self.fence.time_until_ready()
+    }
+}
+
+fn clamp(rate: f64, config: &Aimd) -> f64 {
+    rate.min(config.max_rate).max(config.min_rate)
+}
+
+fn period(rate: f64) -> time::Duration {
+    time::Duration::from_secs_f64(1.0 / rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time;
+    use {AdaptiveFence, Aimd, MockClock};
+
+    const CONFIG: Aimd = Aimd {
+        min_rate: 1.0,
+        max_rate: 100.0,
+        increase: 1.0,
+        decrease: 0.5,
+    };
+
+    #[test]
+    fn additive_increase_multiplicative_decrease() {
+        let mut f = AdaptiveFence::with_clock(CONFIG, 10.0, MockClock::new());
+
+        f.success();
+        f.success();
+        assert_eq!(f.rate(), 12.0);
+        f.overloaded();
+        assert_eq!(f.rate(), 6.0);
+        f.overloaded();
+        f.overloaded();
+        f.overloaded();
+        assert_eq!(f.rate(), 1.0);
+        assert_eq!(f.period(), time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn rate_stays_within_bounds() {
+        let mut f = AdaptiveFence::with_clock(CONFIG, 1000.0, MockClock::new());
+        assert_eq!(f.rate(), 100.0);
+
+        for _ in 0..10 {
+            f.success();
+        }
+        assert_eq!(f.rate(), 100.0);
+        assert_eq!(f.period(), time::Duration::from_millis(10));
+    }
+
+    #[test]
+    fn simulated_dependency_converges_below_capacity() {
+        // A downstream service that overloads above 20 requests per second.
+        let clock = MockClock::new();
+        let mut f = AdaptiveFence::with_clock(CONFIG, 1.0, clock.clone());
+
+        let mut overloads = 0;
+        for _ in 0..2000 {
+            f.sleep();
+            if f.rate() > 20.0 {
+                overloads += 1;
+                f.overloaded();
+            } else {
+                f.success();
+            }
+        }
+        assert!(overloads > 0);
+        assert!(f.rate() >= 10.0);
+        assert!(f.rate() <= 21.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "min_rate must be positive")]
+    fn rejects_zero_min_rate() {
+        AdaptiveFence::with_clock(Aimd { min_rate: 0.0, ..CONFIG }, 1.0, MockClock::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "min_rate must be positive")]
+    fn rejects_nan_min_rate() {
+        AdaptiveFence::with_clock(Aimd { min_rate: f64::NAN, ..CONFIG }, 1.0, MockClock::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "max_rate must be at least min_rate")]
+    fn rejects_inverted_bounds() {
+        AdaptiveFence::with_clock(Aimd { max_rate: 0.5, ..CONFIG }, 1.0, MockClock::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "increase must be finite")]
+    fn rejects_negative_increase() {
+        AdaptiveFence::with_clock(Aimd { increase: -1.0, ..CONFIG }, 1.0, MockClock::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "decrease must be in (0, 1]")]
+    fn rejects_decrease_above_one() {
+        AdaptiveFence::with_clock(Aimd { decrease: 1.5, ..CONFIG }, 1.0, MockClock::new());
+    }
+}
@@ -0,0 +1,290 @@
+use std::time;
+
+use clock::{Clock, MonotonicClock};
+use error::InsufficientCapacity;
+use limiter::RateLimiter;
+
+/// A token bucket rate limiter.
+///
+/// The bucket holds up to `capacity` tokens and gains one token every
+/// `refill` interval. Each permitted event spends a token, so after a quiet
+/// spell a burst of up to `capacity` events passes straight through before
+/// the bucket settles back to one event per `refill`. A new bucket starts
+/// full.
+pub struct TokenBucket<C = MonotonicClock> {
+    clock: C,
+    capacity: u32,
+    refill: time::Duration,
+    tokens: u32,
+    last_refill: time::Instant,
+}
+
+impl TokenBucket {
+
+    /// Construct a bucket holding `capacity` tokens that regains one token
+    /// per `refill` interval. A `capacity` of zero is treated as one.
+    pub fn new(capacity: u32, refill: time::Duration) -> TokenBucket {
+        TokenBucket::with_clock(capacity, refill, MonotonicClock)
+    }
+}
+
+impl<C: Clock> TokenBucket<C> {
+
+    /// Construct a bucket as with `new`, reading time from `clock`.
+    pub fn with_clock(capacity: u32, refill: time::Duration, clock: C) -> TokenBucket<C> {
+        let last_refill = clock.now();
+        let capacity = capacity.max(1);
+        TokenBucket {
+            clock,
+            capacity,
+            refill,
+            tokens: capacity,
+            last_refill,
+        }
+    }
+
+    /// The maximum number of tokens the bucket holds.
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// The number of tokens currently available.
+    pub fn available(&mut self) -> u32 {
+        let now = self.clock.now();
+        self.refill_to(now);
+        self.tokens
+    }
+
+    /// Sleep the current thread until a token is available, then spend it.
+    pub fn sleep(&mut self) {
+        self.wait_and_take(1)
+    }
+
+    /// Sleep the current thread until `n` tokens are available, then spend
+    /// them all at once.
+    ///
+    /// Fails immediately if `n` exceeds the bucket's capacity.
+    pub fn sleep_n(&mut self, n: u32) -> Result<(), InsufficientCapacity> {
+        self.check_capacity(n)?;
+        self.wait_and_take(n);
+        Ok(())
+    }
+
+    /// Spend a token if one is available, without blocking.
+    pub fn allow(&mut self) -> bool {
+        self.try_acquire(1)
+    }
+
+    /// Spend `n` tokens if that many are available, without blocking.
+    ///
+    /// Fails if `n` exceeds the bucket's capacity, distinguishing a request
+    /// that must wait from one that can never succeed.
+    pub fn allow_n(&mut self, n: u32) -> Result<bool, InsufficientCapacity> {
+        self.check_capacity(n)?;
+        Ok(self.try_acquire(n))
+    }
+
+    /// Spend `n` tokens if that many are available, without blocking.
+    ///
+    /// Either all `n` tokens are taken or none are. Returns false if `n`
+    /// exceeds the bucket's capacity, since such a request can never succeed.
+    pub fn try_acquire(&mut self, n: u32) -> bool {
+        let now = self.clock.now();
+        self.refill_to(now);
+        if self.tokens < n {
+            return false;
+        }
+        self.tokens -= n;
+        true
+    }
+
+    fn check_capacity(&self, n: u32) -> Result<(), InsufficientCapacity> {
+        if n > self.capacity {
+            return Err(InsufficientCapacity { requested: n, capacity: self.capacity });
+        }
+        Ok(())
+    }
+
+    fn wait_and_take(&mut self, n: u32) {
+        while !self.try_acquire(n) {
+            let wait = self.wait_for(n);
+            self.clock.sleep(wait);
+        }
+    }
+
+    /// Return `n` tokens to the bucket, up to its capacity.
+    ///
+    /// Useful when a request turned out cheaper than the amount charged for
+    /// it up front.
+    pub fn refund(&mut self, n: u32) {
+        let now = self.clock.now();
+        self.refill_to(now);
+        self.tokens = self.tokens.saturating_add(n).min(self.capacity);
+        if self.tokens == self.capacity {
+            self.last_refill = now;
+        }
+    }
+
+    /// Time until `n` tokens will be available, assuming no other spending.
+    fn wait_for(&self, n: u32) -> time::Duration {
+        if self.tokens >= n {
+            return time::Duration::from_secs(0);
+        }
+        let next = self.last_refill + self.refill * (n - self.tokens);
+        next.saturating_duration_since(self.clock.now())
+    }
+
+    fn refill_to(&mut self, now: time::Instant) {
+        if self.tokens >= self.capacity || self.refill.as_nanos() == 0 {
+            self.tokens = self.capacity;
+            self.last_refill = now;
+            return;
+        }
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let earned = elapsed.as_nanos() / self.refill.as_nanos();
+        let missing = (self.capacity - self.tokens) as u128;
+        if earned >= missing {
+            self.tokens = self.capacity;
+            self.last_refill = now;
+        } else {
+            self.tokens += earned as u32;
+            self.last_refill += self.refill * earned as u32;
+        }
+    }
+}
+
+impl<C: Clock> RateLimiter for TokenBucket<C> {
+    fn try_acquire(&mut self) -> bool {
+        TokenBucket::try_acquire(self, 1)
+    }
+
+    fn acquire(&mut self) {
+        self.sleep()
+    }
+
+    /// Requests larger than the bucket are split into capacity-sized chunks.
+    fn acquire_n(&mut self, n: u32) {
+        let mut remaining = n;
+        while remaining > 0 {
+            let chunk = remaining.min(self.capacity).max(1);
+            self.wait_and_take(chunk);
+            remaining -= chunk;
+        }
+    }
+
+    fn time_until_ready(&self) -> time::Duration {
+        self.wait_for(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time;
+    use {InsufficientCapacity, MockClock, RateLimiter, TokenBucket};
+
+    #[test]
+    fn bucket_allows_burst_then_steady_rate() {
+        let clock = MockClock::new();
+        let mut b = TokenBucket::with_clock(5, time::Duration::from_secs(1), clock.clone());
+
+        for _ in 0..5 {
+            assert!(b.allow());
+        }
+        assert!(!b.allow());
+
+        clock.advance(time::Duration::from_millis(1500));
+        assert!(b.allow());
+        assert!(!b.allow());
+        clock.advance(time::Duration::from_millis(500));
+        assert!(b.allow());
+    }
+
+    #[test]
+    fn bucket_caps_at_capacity() {
+        let clock = MockClock::new();
+        let mut b = TokenBucket::with_clock(3, time::Duration::from_secs(1), clock.clone());
+
+        assert!(b.try_acquire(3));
+        clock.advance(time::Duration::from_secs(60));
+        assert_eq!(b.available(), 3);
+        assert!(!b.try_acquire(4));
+        assert!(b.try_acquire(3));
+    }
+
+    #[test]
+    fn bucket_try_acquire_is_all_or_nothing() {
+        let clock = MockClock::new();
+        let mut b = TokenBucket::with_clock(4, time::Duration::from_secs(1), clock.clone());
+
+        assert!(b.try_acquire(3));
+        assert!(!b.try_acquire(2));
+        assert_eq!(b.available(), 1);
+    }
+
+    #[test]
+    fn bucket_sleep_waits_for_refill() {
+        let clock = MockClock::new();
+        let mut b = TokenBucket::with_clock(2, time::Duration::from_secs(1), clock.clone());
+
+        for _ in 0..5 {
+            b.sleep();
+        }
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(3));
+    }
+
+    #[test]
+    fn bucket_weighted_permits() {
+        let clock = MockClock::new();
+        let mut b = TokenBucket::with_clock(10, time::Duration::from_secs(1), clock.clone());
+
+        assert_eq!(b.allow_n(7), Ok(true));
+        assert_eq!(b.allow_n(7), Ok(false));
+        assert_eq!(b.sleep_n(7), Ok(()));
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(4));
+    }
+
+    #[test]
+    fn bucket_rejects_requests_over_capacity() {
+        let clock = MockClock::new();
+        let mut b = TokenBucket::with_clock(10, time::Duration::from_secs(1), clock.clone());
+        let err = InsufficientCapacity { requested: 11, capacity: 10 };
+
+        assert_eq!(b.allow_n(11), Err(err));
+        assert_eq!(b.sleep_n(11), Err(err));
+        assert_eq!(b.available(), 10);
+    }
+
+    #[test]
+    fn bucket_acquire_n_splits_large_requests() {
+        let clock = MockClock::new();
+        let mut b = TokenBucket::with_clock(10, time::Duration::from_secs(1), clock.clone());
+
+        b.acquire_n(25);
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(15));
+        assert_eq!(b.available(), 0);
+    }
+
+    #[test]
+    fn bucket_refund_returns_tokens() {
+        let clock = MockClock::new();
+        let mut b = TokenBucket::with_clock(10, time::Duration::from_secs(1), clock.clone());
+
+        assert_eq!(b.allow_n(8), Ok(true));
+        b.refund(5);
+        assert_eq!(b.available(), 7);
+        b.refund(50);
+        assert_eq!(b.available(), 10);
+    }
+
+    #[test]
+    fn bucket_zero_capacity_holds_one() {
+        let clock = MockClock::new();
+        let mut b = TokenBucket::with_clock(0, time::Duration::from_secs(1), clock.clone());
+
+        assert_eq!(b.capacity(), 1);
+        b.sleep();
+        b.sleep();
+        b.acquire_n(2);
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(3));
+    }
+}
@@ -0,0 +1,75 @@
+use std::time;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// A handle for interrupting fence waits from another thread.
+///
+/// Clones share the same state: cancelling any clone wakes every wait
+/// started with any other clone, now or in future. A token cannot be reset,
+/// so use a fresh one for each shutdown.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    inner: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl CancelToken {
+
+    /// Construct a token that has not been cancelled.
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Cancel the token, waking all threads waiting on it.
+    pub fn cancel(&self) {
+        let (ref lock, ref cvar) = *self.inner;
+        *lock.lock().unwrap() = true;
+        cvar.notify_all();
+    }
+
+    /// Return true if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        *self.inner.0.lock().unwrap()
+    }
+
+    /// Block the current thread for `dur`, or until the token is cancelled.
+    ///
+    /// Returns true if the wait ended because the token was cancelled.
+    pub fn wait_timeout(&self, dur: time::Duration) -> bool {
+        let (ref lock, ref cvar) = *self.inner;
+        let deadline = time::Instant::now() + dur;
+        let mut cancelled = lock.lock().unwrap();
+        while !*cancelled {
+            let now = time::Instant::now();
+            if now >= deadline {
+                break;
+            }
+            cancelled = cvar.wait_timeout(cancelled, deadline - now).unwrap().0;
+        }
+        *cancelled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time};
+    use CancelToken;
+
+    #[test]
+    fn wait_times_out() {
+        let token = CancelToken::new();
+        assert!(!token.wait_timeout(time::Duration::from_millis(5)));
+        assert!(!token.is_cancelled());
+    }
+
+    #[test]
+    fn cancel_wakes_waiter() {
+        let token = CancelToken::new();
+        let other = token.clone();
+        let before = time::Instant::now();
+
+        let waiter = thread::spawn(move || other.wait_timeout(time::Duration::from_secs(60)));
+        thread::sleep(time::Duration::from_millis(10));
+        token.cancel();
+        assert!(waiter.join().unwrap());
+        assert!(time::Instant::now() - before < time::Duration::from_secs(30));
+    }
+}
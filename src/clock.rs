@@ -0,0 +1,139 @@
+use std::{thread, time};
+use std::sync::{Arc, Mutex};
+
+use cancel::CancelToken;
+use error::Cancelled;
+
+/// A source of time for a limiter.
+///
+/// `Fence` only needs two things from the outside world: the current instant
+/// and the ability to block until some later instant. Abstracting over them
+/// lets tests substitute a `MockClock` and run without real sleeps.
+pub trait Clock {
+    /// Return the current instant.
+    fn now(&self) -> time::Instant;
+
+    /// Block the current thread for the specified duration.
+    fn sleep(&self, dur: time::Duration);
+
+    /// Block the current thread for the specified duration, or until
+    /// `token` is cancelled.
+    ///
+    /// The default implementation only checks the token before sleeping,
+    /// which suits clocks whose sleeps return immediately.
+    fn sleep_cancellable(&self, dur: time::Duration, token: &CancelToken) -> Result<(), Cancelled> {
+        if token.is_cancelled() {
+            return Err(Cancelled);
+        }
+        self.sleep(dur);
+        Ok(())
+    }
+
+    /// Return the current wall-clock time.
+    ///
+    /// Only used to align windows to calendar boundaries; limiters measure
+    /// elapsed time with `now`.
+    fn system_time(&self) -> time::SystemTime {
+        time::SystemTime::now()
+    }
+}
+
+/// The default clock, backed by `Instant::now` and `thread::sleep`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MonotonicClock;
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> time::Instant {
+        time::Instant::now()
+    }
+
+    fn sleep(&self, dur: time::Duration) {
+        thread::sleep(dur)
+    }
+
+    fn sleep_cancellable(&self, dur: time::Duration, token: &CancelToken) -> Result<(), Cancelled> {
+        if token.wait_timeout(dur) {
+            return Err(Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// A manually driven clock for deterministic tests.
+///
+/// Time only moves when `advance` is called, or when something sleeps on the
+/// clock, in which case the sleep returns immediately having advanced virtual
+/// time by the requested duration. Clones share the same virtual time, so a
+/// test can keep one handle while a limiter owns another. Its wall-clock
+/// time starts at the Unix epoch, so calendar alignment is reproducible.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    origin: time::Instant,
+    elapsed: Arc<Mutex<time::Duration>>,
+}
+
+impl MockClock {
+    /// Construct a mock clock starting at the current instant.
+    pub fn new() -> MockClock {
+        MockClock {
+            origin: time::Instant::now(),
+            elapsed: Arc::new(Mutex::new(time::Duration::from_secs(0))),
+        }
+    }
+
+    /// Move virtual time forward by the given duration.
+    pub fn advance(&self, dur: time::Duration) {
+        *self.elapsed.lock().unwrap() += dur;
+    }
+
+    /// The total virtual time elapsed since the clock was constructed.
+    pub fn elapsed(&self) -> time::Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> MockClock {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> time::Instant {
+        self.origin + self.elapsed()
+    }
+
+    fn sleep(&self, dur: time::Duration) {
+        self.advance(dur)
+    }
+
+    fn system_time(&self) -> time::SystemTime {
+        time::UNIX_EPOCH + self.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time;
+    use clock::{Clock, MockClock};
+
+    #[test]
+    fn mock_clock_advances() {
+        let clock = MockClock::new();
+        let start = clock.now();
+
+        clock.advance(time::Duration::from_millis(7));
+        assert_eq!(clock.now() - start, time::Duration::from_millis(7));
+    }
+
+    #[test]
+    fn mock_clock_sleep_is_virtual() {
+        let clock = MockClock::new();
+        let other = clock.clone();
+        let before = time::Instant::now();
+
+        clock.sleep(time::Duration::from_secs(3600));
+        assert_eq!(other.elapsed(), time::Duration::from_secs(3600));
+        assert!(time::Instant::now() - before < time::Duration::from_secs(1));
+    }
+}
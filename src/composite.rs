@@ -0,0 +1,177 @@
+use std::time;
+
+use limiter::RateLimiter;
+
+/// A limiter that grants only when every one of its members would grant.
+///
+/// Useful when several limits apply at once, such as ten requests a second
+/// and a thousand an hour. A denied call consumes nothing from any member,
+/// and a blocking call waits for whichever member will be ready last.
+///
+/// Members are checked with `time_until_ready` before any is charged, so
+/// that guarantee holds only for members whose `time_until_ready` agrees
+/// with `try_acquire`. A limiter shared with other threads may be claimed
+/// between the check and the charge; the composite then denies, but members
+/// charged before the one that refused stay charged.
+pub struct AllOf<L = Box<dyn RateLimiter + Send>> {
+    limiters: Vec<L>,
+}
+
+impl<L: RateLimiter> AllOf<L> {
+
+    /// Construct a composite with no members, which always grants.
+    pub fn new() -> AllOf<L> {
+        AllOf { limiters: Vec::new() }
+    }
+
+    /// Add a member limiter.
+    pub fn push(&mut self, limiter: L) {
+        self.limiters.push(limiter);
+    }
+
+    /// Add a member limiter, returning the composite for chaining.
+    pub fn with(mut self, limiter: L) -> AllOf<L> {
+        self.push(limiter);
+        self
+    }
+
+    /// The member limiters.
+    pub fn limiters(&self) -> &[L] {
+        &self.limiters
+    }
+
+    /// Sleep the current thread until every member grants, then consume a
+    /// permit from each.
+    pub fn sleep(&mut self) {
+        self.acquire()
+    }
+
+    /// Return true and consume a permit from each member if every member
+    /// would grant, without blocking.
+    pub fn allow(&mut self) -> bool {
+        self.try_acquire()
+    }
+}
+
+impl<L: RateLimiter> Default for AllOf<L> {
+    fn default() -> AllOf<L> {
+        AllOf::new()
+    }
+}
+
+impl<L: RateLimiter> RateLimiter for AllOf<L> {
+    fn try_acquire(&mut self) -> bool {
+        let ready = time::Duration::from_secs(0);
+        if self.limiters.iter().any(|l| l.time_until_ready() > ready) {
+            return false;
+        }
+        let granted = self.limiters.iter_mut().all(|l| l.try_acquire());
+        debug_assert!(granted, "a member's try_acquire denied after time_until_ready reported it ready");
+        granted
+    }
+
+    fn acquire(&mut self) {
+        // Wait on the member that will be ready last. Every other member is
+        // ready by the time it releases, so their acquires return at once.
+        let slowest = self.limiters.iter()
+            .enumerate()
+            .max_by_key(|&(_, l)| l.time_until_ready())
+            .map(|(i, _)| i);
+        let slowest = match slowest {
+            Some(i) => i,
+            None => return,
+        };
+        self.limiters[slowest].acquire();
+        for (i, l) in self.limiters.iter_mut().enumerate() {
+            if i != slowest {
+                l.acquire();
+            }
+        }
+    }
+
+    fn time_until_ready(&self) -> time::Duration {
+        self.limiters.iter()
+            .map(|l| l.time_until_ready())
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time;
+    use {AllOf, FixedWindow, MockClock, RateLimiter, TokenBucket};
+
+    fn vendor_limits(clock: &MockClock) -> AllOf {
+        let mut limits: AllOf = AllOf::new();
+        limits.push(Box::new(TokenBucket::with_clock(10, time::Duration::from_millis(100), clock.clone())));
+        limits.push(Box::new(FixedWindow::with_clock(1000, time::Duration::from_secs(3600), clock.clone())));
+        limits.push(Box::new(FixedWindow::with_clock(20000, time::Duration::from_secs(86400), clock.clone())));
+        limits
+    }
+
+    #[test]
+    fn denial_consumes_nothing() {
+        let clock = MockClock::new();
+        let mut per_second = TokenBucket::with_clock(2, time::Duration::from_secs(1), clock.clone());
+        let mut per_minute = FixedWindow::with_clock(3, time::Duration::from_secs(60), clock.clone());
+        {
+            let mut both = AllOf::new().with(&mut per_second as &mut dyn RateLimiter)
+                                       .with(&mut per_minute as &mut dyn RateLimiter);
+            assert!(both.allow());
+            assert!(both.allow());
+            assert!(!both.allow());
+        }
+        // The per-minute window was not charged for the denied call.
+        assert_eq!(per_minute.time_until_ready(), time::Duration::from_secs(0));
+        clock.advance(time::Duration::from_secs(1));
+        assert!(per_minute.allow());
+        assert!(!per_minute.allow());
+    }
+
+    #[test]
+    fn sleep_waits_for_slowest_member() {
+        let clock = MockClock::new();
+        let mut limits = vendor_limits(&clock);
+
+        for _ in 0..1000 {
+            limits.sleep();
+        }
+        // The per-second bucket paces the first thousand calls...
+        assert_eq!(clock.elapsed(), time::Duration::from_millis(99_000));
+        // ...and then the hourly window holds the next one until it resets.
+        assert_eq!(limits.time_until_ready(), time::Duration::from_secs(3600 - 99));
+        limits.sleep();
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(3600));
+    }
+
+    // Claims to be ready but always refuses.
+    struct Inconsistent;
+
+    impl RateLimiter for Inconsistent {
+        fn try_acquire(&mut self) -> bool {
+            false
+        }
+
+        fn acquire(&mut self) {}
+
+        fn time_until_ready(&self) -> time::Duration {
+            time::Duration::from_secs(0)
+        }
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "denied after time_until_ready"))]
+    fn inconsistent_member_is_reported() {
+        let mut limits: AllOf = AllOf::new();
+        limits.push(Box::new(Inconsistent));
+        assert!(!limits.allow());
+    }
+
+    #[test]
+    fn empty_composite_always_grants() {
+        let mut none: AllOf = AllOf::new();
+        assert!(none.allow());
+        assert_eq!(none.time_until_ready(), time::Duration::from_secs(0));
+    }
+}
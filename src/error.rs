@@ -0,0 +1,54 @@
+use std::{error, fmt, time};
+
+/// A request asked for more permits than the limiter can ever hold at once.
+///
+/// Such a request would wait forever, so it is refused up front. Callers
+/// that want it to go through anyway can split it into chunks no larger
+/// than `capacity`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InsufficientCapacity {
+    /// The number of permits requested.
+    pub requested: u32,
+    /// The most permits the limiter can grant in one call.
+    pub capacity: u32,
+}
+
+impl fmt::Display for InsufficientCapacity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "requested {} permits from a limiter with capacity {}",
+               self.requested, self.capacity)
+    }
+}
+
+impl error::Error for InsufficientCapacity {}
+
+/// A bounded wait gave up because the limiter would not open in time.
+///
+/// No permit is consumed when this is returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeadlineExceeded {
+    /// The instant at which the limiter will next open.
+    pub ready_at: time::Instant,
+}
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "limiter would not open before the deadline")
+    }
+}
+
+impl error::Error for DeadlineExceeded {}
+
+/// A wait was interrupted through a `CancelToken`.
+///
+/// No permit is consumed when this is returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "wait was cancelled")
+    }
+}
+
+impl error::Error for Cancelled {}
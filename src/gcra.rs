@@ -0,0 +1,247 @@
+use std::time;
+
+use clock::{Clock, MonotonicClock};
+use error::InsufficientCapacity;
+use limiter::RateLimiter;
+
+/// A limiter implementing the generic cell rate algorithm.
+///
+/// GCRA behaves like a token bucket holding `burst` tokens and regaining one
+/// per `interval`, but its entire state is a single "theoretical arrival
+/// time": the instant at which the limiter would be idle again if no further
+/// events arrived. This is the same idea as `Fence`'s `block_until`, offset
+/// by a burst tolerance, and keeps each limiter small enough to hold very
+/// many of them in a keyed map.
+pub struct Gcra<C = MonotonicClock> {
+    clock: C,
+    interval: time::Duration,
+    tolerance: time::Duration,
+    tat: time::Instant,
+}
+
+impl Gcra {
+
+    /// Construct a limiter permitting one event per `interval` on average,
+    /// with bursts of up to `burst` events. A `burst` of zero is treated as
+    /// one.
+    pub fn new(burst: u32, interval: time::Duration) -> Gcra {
+        Gcra::with_clock(burst, interval, MonotonicClock)
+    }
+}
+
+impl<C: Clock> Gcra<C> {
+
+    /// Construct a limiter as with `new`, reading time from `clock`.
+    pub fn with_clock(burst: u32, interval: time::Duration, clock: C) -> Gcra<C> {
+        let tat = clock.now();
+        Gcra {
+            clock,
+            interval,
+            tolerance: interval * burst.saturating_sub(1),
+            tat,
+        }
+    }
+
+    /// Admit an event if it conforms, without blocking.
+    ///
+    /// On denial, returns the earliest instant at which the next event will
+    /// conform.
+    pub fn check(&mut self) -> Result<(), time::Instant> {
+        let now = self.clock.now();
+        let ready = self.ready_at();
+        if now < ready {
+            return Err(ready);
+        }
+        self.tat = self.tat.max(now) + self.interval;
+        Ok(())
+    }
+
+    /// Return true and admit an event if it conforms, without blocking.
+    pub fn allow(&mut self) -> bool {
+        self.check().is_ok()
+    }
+
+    /// Sleep the current thread until an event conforms, then admit it.
+    pub fn sleep(&mut self) {
+        while let Err(ready) = self.check() {
+            let now = self.clock.now();
+            self.clock.sleep(ready.saturating_duration_since(now));
+        }
+    }
+
+    /// Admit `n` events at once if they all conform, without blocking.
+    ///
+    /// Fails if `n` exceeds the burst size, since such a request can never
+    /// conform.
+    pub fn allow_n(&mut self, n: u32) -> Result<bool, InsufficientCapacity> {
+        self.check_capacity(n)?;
+        if n == 0 {
+            return Ok(true);
+        }
+        let now = self.clock.now();
+        if now < self.ready_at_n(n) {
+            return Ok(false);
+        }
+        self.tat = self.tat.max(now) + self.interval * n;
+        Ok(true)
+    }
+
+    /// Sleep the current thread until `n` events conform, then admit them.
+    ///
+    /// Fails immediately if `n` exceeds the burst size.
+    pub fn sleep_n(&mut self, n: u32) -> Result<(), InsufficientCapacity> {
+        while !self.allow_n(n)? {
+            let now = self.clock.now();
+            self.clock.sleep(self.ready_at_n(n).saturating_duration_since(now));
+        }
+        Ok(())
+    }
+
+    /// Give back `n` previously admitted events, as if they had never
+    /// arrived. The limiter never gains more than a full burst.
+    pub fn refund(&mut self, n: u32) {
+        let now = self.clock.now();
+        let refunded = self.tat.checked_sub(self.interval * n).unwrap_or(now);
+        self.tat = refunded.max(now);
+    }
+
+    /// The largest number of events admitted in one burst.
+    pub fn burst(&self) -> u32 {
+        match self.interval.as_nanos() {
+            0 => u32::MAX,
+            i => (self.tolerance.as_nanos() / i + 1).min(u32::MAX as u128) as u32,
+        }
+    }
+
+    fn check_capacity(&self, n: u32) -> Result<(), InsufficientCapacity> {
+        let burst = self.burst();
+        if n > burst {
+            return Err(InsufficientCapacity { requested: n, capacity: burst });
+        }
+        Ok(())
+    }
+
+    /// The earliest instant at which the next event will conform.
+    fn ready_at(&self) -> time::Instant {
+        self.ready_at_n(1)
+    }
+
+    /// The earliest instant at which `n` more events will conform together.
+    fn ready_at_n(&self, n: u32) -> time::Instant {
+        let last = self.tat + self.interval * n.saturating_sub(1);
+        last.checked_sub(self.tolerance).unwrap_or(last)
+    }
+}
+
+impl<C: Clock> RateLimiter for Gcra<C> {
+    fn try_acquire(&mut self) -> bool {
+        self.allow()
+    }
+
+    fn acquire(&mut self) {
+        self.sleep()
+    }
+
+    /// Requests larger than the burst size are split into burst-sized
+    /// chunks.
+    fn acquire_n(&mut self, n: u32) {
+        let burst = self.burst();
+        let mut remaining = n;
+        while remaining > 0 {
+            let chunk = remaining.min(burst);
+            let _ = self.sleep_n(chunk);
+            remaining -= chunk;
+        }
+    }
+
+    fn time_until_ready(&self) -> time::Duration {
+        self.ready_at().saturating_duration_since(self.clock.now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{mem, time};
+    use {Clock, Gcra, InsufficientCapacity, MockClock, RateLimiter};
+
+    #[test]
+    fn gcra_allows_burst_then_steady_rate() {
+        let clock = MockClock::new();
+        let mut g = Gcra::with_clock(3, time::Duration::from_secs(1), clock.clone());
+
+        assert!(g.allow());
+        assert!(g.allow());
+        assert!(g.allow());
+        assert!(!g.allow());
+
+        clock.advance(time::Duration::from_secs(1));
+        assert!(g.allow());
+        assert!(!g.allow());
+    }
+
+    #[test]
+    fn gcra_reports_next_conforming_instant() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut g = Gcra::with_clock(1, time::Duration::from_millis(250), clock.clone());
+
+        assert_eq!(g.check(), Ok(()));
+        assert_eq!(g.check(), Err(start + time::Duration::from_millis(250)));
+        clock.advance(time::Duration::from_millis(100));
+        assert_eq!(g.check(), Err(start + time::Duration::from_millis(250)));
+        clock.advance(time::Duration::from_millis(150));
+        assert_eq!(g.check(), Ok(()));
+    }
+
+    #[test]
+    fn gcra_sleep_paces_events() {
+        let clock = MockClock::new();
+        let mut g = Gcra::with_clock(2, time::Duration::from_secs(1), clock.clone());
+
+        for _ in 0..6 {
+            g.sleep();
+        }
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(4));
+    }
+
+    #[test]
+    fn gcra_state_is_compact() {
+        assert!(mem::size_of::<Gcra>() <= 48);
+    }
+
+    #[test]
+    fn gcra_weighted_permits() {
+        let clock = MockClock::new();
+        let mut g = Gcra::with_clock(10, time::Duration::from_secs(1), clock.clone());
+
+        assert_eq!(g.burst(), 10);
+        assert_eq!(g.allow_n(7), Ok(true));
+        assert_eq!(g.allow_n(7), Ok(false));
+        assert_eq!(g.sleep_n(7), Ok(()));
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(4));
+        assert_eq!(g.allow_n(11), Err(InsufficientCapacity { requested: 11, capacity: 10 }));
+    }
+
+    #[test]
+    fn gcra_acquire_n_splits_large_requests() {
+        let clock = MockClock::new();
+        let mut g = Gcra::with_clock(10, time::Duration::from_secs(1), clock.clone());
+
+        g.acquire_n(25);
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(15));
+    }
+
+    #[test]
+    fn gcra_refund_returns_capacity() {
+        let clock = MockClock::new();
+        let mut g = Gcra::with_clock(10, time::Duration::from_secs(1), clock.clone());
+
+        assert_eq!(g.allow_n(10), Ok(true));
+        assert!(!g.allow());
+        g.refund(3);
+        assert_eq!(g.allow_n(3), Ok(true));
+        assert!(!g.allow());
+        g.refund(50);
+        assert_eq!(g.allow_n(10), Ok(true));
+    }
+}
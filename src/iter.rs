@@ -0,0 +1,121 @@
+use limiter::RateLimiter;
+
+/// Extension methods for pacing an iterator with a limiter.
+///
+/// Any `RateLimiter` works, including `&mut` references to one, so a single
+/// fence can be lent to several iterators in turn.
+pub trait RateLimitedExt: Iterator + Sized {
+    /// Yield each item only once `limiter` grants a permit, blocking the
+    /// current thread as needed.
+    fn rate_limited<L: RateLimiter>(self, limiter: L) -> RateLimited<Self, L> {
+        RateLimited { iter: self, limiter }
+    }
+
+    /// Yield `Some(item)` for each item `limiter` grants a permit to
+    /// without waiting, and `None` in place of each item it denies.
+    ///
+    /// Denied items are dropped. Chain `.flatten()` to skip them entirely.
+    fn try_rate_limited<L: RateLimiter>(self, limiter: L) -> TryRateLimited<Self, L> {
+        TryRateLimited { iter: self, limiter }
+    }
+}
+
+impl<I: Iterator> RateLimitedExt for I {}
+
+/// Iterator returned by `RateLimitedExt::rate_limited`.
+pub struct RateLimited<I, L> {
+    iter: I,
+    limiter: L,
+}
+
+impl<I, L> RateLimited<I, L> {
+
+    /// Unwrap the iterator and limiter.
+    pub fn into_inner(self) -> (I, L) {
+        (self.iter, self.limiter)
+    }
+}
+
+impl<I: Iterator, L: RateLimiter> Iterator for RateLimited<I, L> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let item = self.iter.next()?;
+        self.limiter.acquire();
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+/// Iterator returned by `RateLimitedExt::try_rate_limited`.
+pub struct TryRateLimited<I, L> {
+    iter: I,
+    limiter: L,
+}
+
+impl<I, L> TryRateLimited<I, L> {
+
+    /// Unwrap the iterator and limiter.
+    pub fn into_inner(self) -> (I, L) {
+        (self.iter, self.limiter)
+    }
+}
+
+impl<I: Iterator, L: RateLimiter> Iterator for TryRateLimited<I, L> {
+    type Item = Option<I::Item>;
+
+    fn next(&mut self) -> Option<Option<I::Item>> {
+        let item = self.iter.next()?;
+        if self.limiter.try_acquire() {
+            Some(Some(item))
+        } else {
+            Some(None)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time;
+    use {Fence, MockClock, RateLimitedExt, TokenBucket};
+
+    #[test]
+    fn rate_limited_paces_items() {
+        let clock = MockClock::new();
+        let fence = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        let items: Vec<_> = (0..10).rate_limited(fence).collect();
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(10));
+    }
+
+    #[test]
+    fn rate_limited_borrows_limiter() {
+        let clock = MockClock::new();
+        let mut fence = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        assert_eq!((0..3).rate_limited(&mut fence).count(), 3);
+        assert_eq!((0..2).rate_limited(&mut fence).count(), 2);
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn try_rate_limited_drops_denied_items() {
+        let clock = MockClock::new();
+        let bucket = TokenBucket::with_clock(2, time::Duration::from_secs(1), clock.clone());
+
+        // One item arrives every 400ms.
+        let tick = clock.clone();
+        let arrivals = (0..10).inspect(move |_| tick.advance(time::Duration::from_millis(400)));
+        let kept: Vec<_> = arrivals.try_rate_limited(bucket).flatten().collect();
+        assert_eq!(kept, vec![0, 1, 3, 5, 8]);
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(4));
+    }
+}
@@ -0,0 +1,203 @@
+use std::time;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use clock::{Clock, MonotonicClock};
+
+/// Automatic eviction never runs while fewer keys than this are tracked.
+const MIN_SWEEP: usize = 64;
+
+struct KeyState {
+    block_until: time::Instant,
+    last_seen: time::Instant,
+}
+
+/// A set of fences, one per key, sharing a single period.
+///
+/// State for a key is created the first time it is seen. Unlike a fresh
+/// `Fence`, a fresh key is open: the first call for each user or API key
+/// goes straight through. A key whose fence has reopened is indistinguishable
+/// from one never seen, so it is evicted to keep memory bounded; an optional
+/// TTL also evicts keys that have not been seen for a while regardless of
+/// their state. Eviction runs automatically as the map grows and can be
+/// forced with `evict`.
+pub struct KeyedFence<K, C = MonotonicClock> {
+    clock: C,
+    duration: time::Duration,
+    ttl: Option<time::Duration>,
+    states: HashMap<K, KeyState>,
+    sweep_at: usize,
+}
+
+impl<K: Eq + Hash + Clone> KeyedFence<K> {
+
+    /// Construct a keyed fence admitting one call per key per `dur`.
+    pub fn new(dur: time::Duration) -> KeyedFence<K> {
+        KeyedFence::with_clock(dur, MonotonicClock)
+    }
+}
+
+impl<K: Eq + Hash + Clone, C: Clock> KeyedFence<K, C> {
+
+    /// Construct a keyed fence as with `new`, reading time from `clock`.
+    pub fn with_clock(dur: time::Duration, clock: C) -> KeyedFence<K, C> {
+        KeyedFence {
+            clock,
+            duration: dur,
+            ttl: None,
+            states: HashMap::new(),
+            sweep_at: MIN_SWEEP,
+        }
+    }
+
+    /// Evict keys not seen for `ttl`, even if their fence is still closed.
+    pub fn set_ttl(&mut self, ttl: time::Duration) {
+        self.ttl = Some(ttl);
+    }
+
+    /// The number of keys currently tracked.
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Return true if no keys are tracked.
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /// Return true and consume the slot for `key` if its fence is open,
+    /// without blocking.
+    pub fn allow(&mut self, key: &K) -> bool {
+        let now = self.clock.now();
+        if let Some(state) = self.states.get_mut(key) {
+            state.last_seen = now;
+            if now < state.block_until {
+                return false;
+            }
+            state.block_until = now + self.duration;
+            return true;
+        }
+        self.insert(key, now);
+        true
+    }
+
+    /// Sleep the current thread until the fence for `key` opens, then
+    /// consume its slot.
+    pub fn sleep(&mut self, key: &K) {
+        let wait = self.time_until_ready(key);
+        if wait > time::Duration::from_secs(0) {
+            self.clock.sleep(wait);
+        }
+        let now = self.clock.now();
+        match self.states.get_mut(key) {
+            Some(state) => {
+                state.block_until = now + self.duration;
+                state.last_seen = now;
+            }
+            None => self.insert(key, now),
+        }
+    }
+
+    /// How long until the fence for `key` opens, or zero if it is open.
+    pub fn time_until_ready(&self, key: &K) -> time::Duration {
+        match self.states.get(key) {
+            Some(state) => state.block_until.saturating_duration_since(self.clock.now()),
+            None => time::Duration::from_secs(0),
+        }
+    }
+
+    /// Drop every key that is idle or has outlived the TTL, returning how
+    /// many were dropped.
+    pub fn evict(&mut self) -> usize {
+        let now = self.clock.now();
+        let ttl = self.ttl;
+        let before = self.states.len();
+        self.states.retain(|_, state| {
+            let expired = ttl.is_some_and(|ttl| now.saturating_duration_since(state.last_seen) >= ttl);
+            now < state.block_until && !expired
+        });
+        before - self.states.len()
+    }
+
+    fn insert(&mut self, key: &K, now: time::Instant) {
+        self.states.insert(key.clone(), KeyState {
+            block_until: now + self.duration,
+            last_seen: now,
+        });
+        if self.states.len() >= self.sweep_at {
+            self.evict();
+            self.sweep_at = MIN_SWEEP.max(self.states.len() * 2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time;
+    use {KeyedFence, MockClock};
+
+    #[test]
+    fn keys_are_limited_independently() {
+        let clock = MockClock::new();
+        let mut f = KeyedFence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        assert!(f.allow(&"alice"));
+        assert!(!f.allow(&"alice"));
+        assert!(f.allow(&"bob"));
+
+        clock.advance(time::Duration::from_secs(1));
+        assert!(f.allow(&"alice"));
+    }
+
+    #[test]
+    fn sleep_waits_per_key() {
+        let clock = MockClock::new();
+        let mut f = KeyedFence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        f.sleep(&1);
+        f.sleep(&2);
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(0));
+        f.sleep(&1);
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn idle_keys_are_evicted() {
+        let clock = MockClock::new();
+        let mut f = KeyedFence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        f.allow(&"alice");
+        f.allow(&"bob");
+        assert_eq!(f.evict(), 0);
+        clock.advance(time::Duration::from_secs(1));
+        assert_eq!(f.evict(), 2);
+        assert!(f.is_empty());
+    }
+
+    #[test]
+    fn ttl_evicts_pending_keys() {
+        let clock = MockClock::new();
+        let mut f = KeyedFence::with_clock(time::Duration::from_secs(3600), clock.clone());
+        f.set_ttl(time::Duration::from_secs(60));
+
+        f.allow(&"alice");
+        clock.advance(time::Duration::from_secs(30));
+        f.allow(&"bob");
+        clock.advance(time::Duration::from_secs(30));
+        assert_eq!(f.evict(), 1);
+        assert!(f.allow(&"alice"));
+        assert!(!f.allow(&"bob"));
+    }
+
+    #[test]
+    fn memory_stays_bounded() {
+        let clock = MockClock::new();
+        let mut f = KeyedFence::with_clock(time::Duration::from_millis(10), clock.clone());
+
+        for key in 0..100_000u32 {
+            f.allow(&key);
+            clock.advance(time::Duration::from_millis(1));
+        }
+        assert!(f.len() < 200);
+    }
+}
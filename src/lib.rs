@@ -1,14 +1,106 @@
-use std::{thread, time};
-use std::marker::{Send, Sync};
+use std::time;
 
-pub struct Fence {
+#[cfg(feature = "stream")]
+extern crate futures_core;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+
+pub mod adaptive;
+pub mod bucket;
+pub mod cancel;
+pub mod clock;
+pub mod composite;
+pub mod error;
+pub mod gcra;
+pub mod iter;
+pub mod keyed;
+pub mod limiter;
+#[cfg(feature = "async")]
+pub mod ready;
+pub mod reservation;
+pub mod shared;
+pub mod sharded;
+pub mod stats;
+#[cfg(feature = "stream")]
+pub mod stream;
+#[cfg(all(test, feature = "async"))]
+mod testing;
+pub mod throttled;
+#[cfg(feature = "async")]
+mod timer;
+pub mod window;
+
+pub use adaptive::{AdaptiveFence, Aimd};
+pub use bucket::TokenBucket;
+pub use cancel::CancelToken;
+pub use clock::{Clock, MockClock, MonotonicClock};
+pub use composite::AllOf;
+pub use error::{Cancelled, DeadlineExceeded, InsufficientCapacity};
+pub use gcra::Gcra;
+pub use iter::RateLimitedExt;
+pub use keyed::KeyedFence;
+pub use limiter::RateLimiter;
+#[cfg(feature = "async")]
+pub use ready::Ready;
+pub use reservation::Reservation;
+pub use shared::SharedFence;
+pub use sharded::ShardedKeyedFence;
+pub use stats::{Instrumented, Stats};
+#[cfg(feature = "stream")]
+pub use stream::{Overflow, RateLimitedStreamExt};
+pub use throttled::{ThrottledReader, ThrottledWriter};
+pub use window::{FixedWindow, SlidingWindowCounter, SlidingWindowLog};
+
+/// How a fence picks its next deadline after letting a caller through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// The next deadline is one period after the caller was released, so
+    /// time spent oversleeping or running the loop body pushes every later
+    /// deadline back. This is the default.
+    FixedDelay,
+    /// The next deadline is one period after the previous deadline, so the
+    /// long-run rate stays exact regardless of oversleep.
+    FixedRate,
+}
+
+/// What a fixed-rate fence does when a caller arrives after one or more
+/// deadlines have already passed.
+///
+/// A fixed-delay fence always behaves as `Delay`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissedTick {
+    /// Release callers back to back until the schedule has caught up. This
+    /// is the default.
+    Burst,
+    /// Drop the missed deadlines and resume at the next deadline on the
+    /// original schedule.
+    Skip,
+    /// Restart the schedule one period after the late release.
+    Delay,
+}
+
+/// What happened during a call to `Fence::sleep_report`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SleepReport {
+    /// How long the caller was blocked.
+    pub slept: time::Duration,
+    /// How far past the deadline the caller was released. Includes both
+    /// oversleep and any time the caller arrived after the deadline.
+    pub lateness: time::Duration,
+    /// How many whole periods had elapsed past the deadline on release.
+    pub missed: u32,
+}
+
+pub struct Fence<C = MonotonicClock> {
+  clock: C,
   duration: time::Duration,
   block_until: time::Instant,
+  // How many periods `block_until` lies beyond the release that set it.
+  charged: u32,
+  schedule: Schedule,
+  missed_tick: MissedTick,
 }
 
-unsafe impl Send for Fence {}
-unsafe impl Sync for Fence {}
-
 /// Fence provides a timed rate limiter. It's useful for imposing a framerate
 /// cap on a UI thread, or enforcing periodicity on a polling thread.
 /// 
@@ -28,35 +120,283 @@ impl Fence {
 
     /// Construct a fence from the given duration.
     pub fn from_duration(dur: time::Duration) -> Fence {
+        Fence::with_clock(dur, MonotonicClock)
+    }
+}
+
+impl<C: Clock> Fence<C> {
+
+    /// Construct a fence from the given duration, reading time from `clock`.
+    pub fn with_clock(dur: time::Duration, clock: C) -> Fence<C> {
+        let block_until = clock.now() + dur;
         Fence {
+            clock,
             duration: dur,
-            block_until: time::Instant::now() + dur,
+            block_until,
+            charged: 1,
+            schedule: Schedule::FixedDelay,
+            missed_tick: MissedTick::Burst,
         }
     }
 
+    /// The period between slots.
+    pub fn duration(&self) -> time::Duration {
+        self.duration
+    }
+
+    /// Change the period between slots in place.
+    ///
+    /// A pending deadline is moved so that it falls the new period after
+    /// the release that set it, rather than the old one: shortening the
+    /// period opens the fence sooner, and lengthening it holds the fence
+    /// closed for longer, as though the new rate had always applied. A
+    /// deadline extended by `allow_n` or `sleep_n` is rescaled in full, so
+    /// `n` permits still cost `n` of the new periods.
+    pub fn set_duration(&mut self, dur: time::Duration) {
+        let released = self.block_until.checked_sub(self.duration * self.charged);
+        if let Some(released) = released {
+            self.block_until = released + dur * self.charged;
+        }
+        self.duration = dur;
+    }
+
+    /// The scheduling mode used to pick deadlines.
+    pub fn schedule(&self) -> Schedule {
+        self.schedule
+    }
+
+    /// Change the scheduling mode. Takes effect from the next deadline.
+    pub fn set_schedule(&mut self, schedule: Schedule) {
+        self.schedule = schedule;
+    }
+
+    /// The policy for deadlines missed under `Schedule::FixedRate`.
+    pub fn missed_tick(&self) -> MissedTick {
+        self.missed_tick
+    }
+
+    /// Change the policy for deadlines missed under `Schedule::FixedRate`.
+    pub fn set_missed_tick(&mut self, missed_tick: MissedTick) {
+        self.missed_tick = missed_tick;
+    }
+
     /// Sleep the current thread until at least the specified passage of time.
     pub fn sleep(&mut self) {
-        let now = time::Instant::now();
+        self.sleep_report();
+    }
+
+    /// Sleep as with `sleep`, reporting how long the caller waited and how
+    /// late it was released.
+    pub fn sleep_report(&mut self) -> SleepReport {
+        let start = self.clock.now();
+        let deadline = self.block_until;
+        if start < deadline {
+          self.clock.sleep(deadline.duration_since(start))
+        }
+        let now = self.clock.now();
+        self.release(now);
+
+        let lateness = now.saturating_duration_since(deadline);
+        let missed = match self.duration.as_nanos() {
+            0 => 0,
+            period => (lateness.as_nanos() / period).min(u32::MAX as u128) as u32,
+        };
+        SleepReport {
+            slept: now.saturating_duration_since(start),
+            lateness,
+            missed,
+        }
+    }
+
+    /// Sleep as with `sleep`, but return early if `token` is cancelled.
+    ///
+    /// A cancelled wait does not consume the slot. A token that was
+    /// cancelled before the call fails immediately, even if the fence is
+    /// open.
+    pub fn sleep_cancellable(&mut self, token: &CancelToken) -> Result<(), Cancelled> {
+        if token.is_cancelled() {
+            return Err(Cancelled);
+        }
+        let now = self.clock.now();
         if now < self.block_until {
-          thread::sleep(self.block_until.duration_since(now))
+            self.clock.sleep_cancellable(self.block_until.duration_since(now), token)?;
         }
-        self.block_until = time::Instant::now() + self.duration;
+        let now = self.clock.now();
+        self.release(now);
+        Ok(())
+    }
+
+    /// Sleep as with `sleep`, but only if the fence will open within
+    /// `timeout`.
+    ///
+    /// Returns an error immediately, without waiting or consuming the slot,
+    /// if the fence would still be closed once `timeout` has elapsed.
+    pub fn sleep_timeout(&mut self, timeout: time::Duration) -> Result<(), DeadlineExceeded> {
+        let deadline = self.clock.now() + timeout;
+        self.sleep_until_deadline(deadline)
+    }
+
+    /// Sleep as with `sleep`, but only if the fence will open by `deadline`.
+    ///
+    /// Returns an error immediately, without waiting or consuming the slot,
+    /// if the fence would still be closed at `deadline`.
+    pub fn sleep_until_deadline(&mut self, deadline: time::Instant) -> Result<(), DeadlineExceeded> {
+        if self.block_until > deadline {
+            return Err(DeadlineExceeded { ready_at: self.block_until });
+        }
+        self.sleep();
+        Ok(())
+    }
+
+    /// Sleep until the fence opens, then hold it closed for `n` periods
+    /// rather than one, so that a single call can account for `n` events.
+    ///
+    /// Only the first period is waited out here; the rest is charged to the
+    /// next caller. `RateLimiter::acquire_n` behaves the same way.
+    pub fn sleep_n(&mut self, n: u32) {
+        if n == 0 {
+            return;
+        }
+        self.sleep();
+        self.charge(n - 1);
     }
 
     pub fn allow(&mut self) -> bool {
-        let now = time::Instant::now();
+        let now = self.clock.now();
         if now < self.block_until {
             return false;
         }
-        self.block_until = now + self.duration;
+        self.release(now);
         true
     }
+
+    /// Claim the next slot without blocking.
+    ///
+    /// The returned reservation says when the slot becomes valid and can be
+    /// cancelled to give the slot back.
+    pub fn reserve(&mut self) -> Reservation {
+        let now = self.clock.now();
+        let previous = (self.block_until, self.charged);
+        let ready_at = self.block_until.max(now);
+        self.release(ready_at);
+        Reservation::new(ready_at, previous, self.block_until)
+    }
+
+    /// Sleep the current thread until `reservation`'s slot becomes valid.
+    pub fn wait(&self, reservation: Reservation) {
+        let now = self.clock.now();
+        if now < reservation.ready_at() {
+            self.clock.sleep(reservation.ready_at() - now);
+        }
+    }
+
+    /// Give `reservation`'s slot back, returning true if it was restored.
+    ///
+    /// The fence is rolled back only if nothing has claimed or reconfigured
+    /// it since the reservation was made; otherwise later callers are
+    /// already queued behind the slot, which stays charged. Reservations
+    /// cancelled newest first all succeed.
+    pub fn cancel(&mut self, reservation: Reservation) -> bool {
+        if self.block_until != reservation.next() {
+            return false;
+        }
+        let (block_until, charged) = reservation.previous();
+        self.block_until = block_until;
+        self.charged = charged;
+        true
+    }
+
+    /// Return true if the fence is open and hold it closed for `n` periods
+    /// rather than one. Asking for zero permits always succeeds.
+    pub fn allow_n(&mut self, n: u32) -> bool {
+        if n == 0 {
+            return true;
+        }
+        if !self.allow() {
+            return false;
+        }
+        self.charge(n - 1);
+        true
+    }
+
+    /// Let a caller through at `now`, setting the following deadline.
+    fn release(&mut self, now: time::Instant) {
+        self.block_until = self.next_deadline(now);
+        self.charged = 1;
+    }
+
+    /// Hold the fence closed for `n` more periods.
+    fn charge(&mut self, n: u32) {
+        self.block_until += self.duration * n;
+        self.charged = self.charged.saturating_add(n);
+    }
+
+    /// The deadline following a release at `now`.
+    fn next_deadline(&self, now: time::Instant) -> time::Instant {
+        match self.schedule {
+            Schedule::FixedDelay => now + self.duration,
+            Schedule::FixedRate => {
+                let next = self.block_until + self.duration;
+                if next > now {
+                    return next;
+                }
+                match self.missed_tick {
+                    MissedTick::Burst => next,
+                    MissedTick::Delay => now + self.duration,
+                    MissedTick::Skip => {
+                        let period = self.duration.as_nanos();
+                        if period == 0 {
+                            return now;
+                        }
+                        let missed = (now - next).as_nanos() / period + 1;
+                        next + time::Duration::from_nanos((missed * period) as u64)
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<C: Clock> RateLimiter for Fence<C> {
+    fn try_acquire(&mut self) -> bool {
+        self.allow()
+    }
+
+    fn acquire(&mut self) {
+        self.sleep()
+    }
+
+    /// Waits for one period and charges the rest to the next caller, as
+    /// `sleep_n` does.
+    fn acquire_n(&mut self, n: u32) {
+        self.sleep_n(n)
+    }
+
+    fn time_until_ready(&self) -> time::Duration {
+        self.block_until.saturating_duration_since(self.clock.now())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::time;
-    use Fence;
+    use std::thread;
+    use {CancelToken, Cancelled, Clock, DeadlineExceeded, Fence, MissedTick, MockClock, RateLimiter,
+         Schedule, SleepReport};
+
+    // A clock that oversleeps by a fixed amount, like a loaded OS scheduler.
+    #[derive(Clone)]
+    struct LateClock(MockClock, time::Duration);
+
+    impl Clock for LateClock {
+        fn now(&self) -> time::Instant {
+            self.0.now()
+        }
+
+        fn sleep(&self, dur: time::Duration) {
+            self.0.sleep(dur + self.1)
+        }
+    }
 
     #[test]
     fn fence_blocks() {
@@ -82,4 +422,250 @@ mod tests {
         let after = time::Instant::now();
         assert!(after >= before + fence_dur * lim);
     }
+
+    #[test]
+    fn mock_fence_rate_limits() {
+        let clock = MockClock::new();
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        for _ in 0..100 {
+          f.sleep()
+        }
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(100));
+    }
+
+    #[test]
+    fn mock_fence_allow() {
+        let clock = MockClock::new();
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        assert!(!f.allow());
+        clock.advance(time::Duration::from_millis(999));
+        assert!(!f.allow());
+        clock.advance(time::Duration::from_millis(1));
+        assert!(f.allow());
+        assert!(!f.allow());
+    }
+
+    #[test]
+    fn fixed_delay_accumulates_oversleep() {
+        let clock = MockClock::new();
+        let late = LateClock(clock.clone(), time::Duration::from_millis(5));
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), late);
+
+        for _ in 0..10 {
+          f.sleep()
+        }
+        assert_eq!(clock.elapsed(), time::Duration::from_millis(10050));
+    }
+
+    #[test]
+    fn fixed_rate_does_not_drift() {
+        let clock = MockClock::new();
+        let late = LateClock(clock.clone(), time::Duration::from_millis(5));
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), late);
+        f.set_schedule(Schedule::FixedRate);
+
+        for _ in 0..10 {
+          clock.advance(time::Duration::from_millis(300));
+          f.sleep()
+        }
+        assert_eq!(clock.elapsed(), time::Duration::from_millis(10005));
+    }
+
+    // Runs a 1s fixed-rate loop whose third iteration overruns by 3.5s, and
+    // returns the offsets at which the fence released the caller.
+    fn overrun_releases(policy: MissedTick) -> Vec<time::Duration> {
+        let clock = MockClock::new();
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+        f.set_schedule(Schedule::FixedRate);
+        f.set_missed_tick(policy);
+
+        let mut releases = Vec::new();
+        for i in 0..6 {
+          if i == 2 {
+            clock.advance(time::Duration::from_millis(3500));
+          }
+          f.sleep();
+          releases.push(clock.elapsed());
+        }
+        releases
+    }
+
+    fn millis(ms: &[u64]) -> Vec<time::Duration> {
+        ms.iter().map(|&m| time::Duration::from_millis(m)).collect()
+    }
+
+    #[test]
+    fn missed_tick_burst_catches_up() {
+        assert_eq!(overrun_releases(MissedTick::Burst),
+                   millis(&[1000, 2000, 5500, 5500, 5500, 6000]));
+    }
+
+    #[test]
+    fn missed_tick_skip_keeps_phase() {
+        assert_eq!(overrun_releases(MissedTick::Skip),
+                   millis(&[1000, 2000, 5500, 6000, 7000, 8000]));
+    }
+
+    #[test]
+    fn missed_tick_delay_shifts_schedule() {
+        assert_eq!(overrun_releases(MissedTick::Delay),
+                   millis(&[1000, 2000, 5500, 6500, 7500, 8500]));
+    }
+
+    #[test]
+    fn sleep_report_on_time() {
+        let clock = MockClock::new();
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        clock.advance(time::Duration::from_millis(400));
+        assert_eq!(f.sleep_report(), SleepReport {
+            slept: time::Duration::from_millis(600),
+            lateness: time::Duration::from_secs(0),
+            missed: 0,
+        });
+    }
+
+    #[test]
+    fn sleep_report_overrun() {
+        let clock = MockClock::new();
+        let late = LateClock(clock.clone(), time::Duration::from_millis(5));
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), late);
+
+        assert_eq!(f.sleep_report().lateness, time::Duration::from_millis(5));
+
+        clock.advance(time::Duration::from_millis(3200));
+        assert_eq!(f.sleep_report(), SleepReport {
+            slept: time::Duration::from_secs(0),
+            lateness: time::Duration::from_millis(2200),
+            missed: 2,
+        });
+    }
+
+    #[test]
+    fn allow_n_holds_fence_for_n_periods() {
+        let clock = MockClock::new();
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        clock.advance(time::Duration::from_secs(1));
+        assert!(f.allow_n(50));
+        clock.advance(time::Duration::from_secs(49));
+        assert!(!f.allow());
+        clock.advance(time::Duration::from_secs(1));
+        assert!(f.allow());
+    }
+
+    #[test]
+    fn sleep_n_charges_following_caller() {
+        let clock = MockClock::new();
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        f.sleep_n(5);
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(1));
+        f.sleep();
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(6));
+    }
+
+    #[test]
+    fn acquire_n_matches_sleep_n() {
+        let clock = MockClock::new();
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        RateLimiter::acquire_n(&mut f, 5);
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(1));
+        f.sleep();
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(6));
+    }
+
+    #[test]
+    fn sleep_timeout_sheds_without_consuming() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        assert_eq!(f.sleep_timeout(time::Duration::from_millis(500)),
+                   Err(DeadlineExceeded { ready_at: start + time::Duration::from_secs(1) }));
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(0));
+
+        assert_eq!(f.sleep_timeout(time::Duration::from_secs(1)), Ok(()));
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn sleep_until_deadline() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        assert!(f.sleep_until_deadline(start + time::Duration::from_millis(999)).is_err());
+        assert!(f.sleep_until_deadline(start + time::Duration::from_secs(5)).is_ok());
+        assert!(f.sleep_until_deadline(start + time::Duration::from_secs(5)).is_ok());
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn cancelled_sleep_keeps_slot() {
+        let clock = MockClock::new();
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+        let token = CancelToken::new();
+
+        assert_eq!(f.sleep_cancellable(&token), Ok(()));
+        token.cancel();
+        assert_eq!(f.sleep_cancellable(&token), Err(Cancelled));
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(1));
+
+        clock.advance(time::Duration::from_secs(1));
+        assert!(f.allow());
+    }
+
+    #[test]
+    fn cancel_interrupts_sleep() {
+        let mut f = Fence::from_secs(60);
+        let token = CancelToken::new();
+        let canceller = token.clone();
+        let before = time::Instant::now();
+
+        let t = thread::spawn(move || {
+          thread::sleep(time::Duration::from_millis(10));
+          canceller.cancel();
+        });
+        assert_eq!(f.sleep_cancellable(&token), Err(Cancelled));
+        assert!(time::Instant::now() - before < time::Duration::from_secs(30));
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn set_duration_moves_pending_deadline() {
+        let clock = MockClock::new();
+        let mut f = Fence::with_clock(time::Duration::from_secs(10), clock.clone());
+
+        clock.advance(time::Duration::from_secs(3));
+        f.set_duration(time::Duration::from_secs(4));
+        assert_eq!(f.duration(), time::Duration::from_secs(4));
+        f.sleep();
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(4));
+
+        f.set_duration(time::Duration::from_secs(1));
+        f.sleep();
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn set_duration_rescales_weighted_debt() {
+        let clock = MockClock::new();
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        clock.advance(time::Duration::from_secs(1));
+        assert!(f.allow_n(50));
+        f.set_duration(time::Duration::from_secs(2));
+        f.sleep();
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(101));
+
+        let r = f.reserve();
+        assert!(f.cancel(r));
+        f.set_duration(time::Duration::from_secs(1));
+        f.sleep();
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(102));
+    }
 }
@@ -0,0 +1,122 @@
+use std::time;
+
+/// The operations common to every limiter in this crate.
+///
+/// Code that only needs "some limiter" can be written against this trait
+/// and handed a `Fence`, a `TokenBucket`, or any other algorithm chosen by
+/// configuration.
+pub trait RateLimiter {
+    /// Consume a permit if one is available, without blocking.
+    fn try_acquire(&mut self) -> bool;
+
+    /// Block the current thread until a permit is available, then consume
+    /// it.
+    fn acquire(&mut self);
+
+    /// Block the current thread until `n` permits have been consumed.
+    ///
+    /// By default this waits for each permit in turn. A limiter may instead
+    /// release the caller once the first permit is available and charge the
+    /// rest to later callers, as `Fence` does; either way, the next `n`
+    /// permits' worth of time is spent once this returns.
+    fn acquire_n(&mut self, n: u32) {
+        for _ in 0..n {
+            self.acquire();
+        }
+    }
+
+    /// How long until `try_acquire` would succeed, or zero if it would
+    /// succeed now.
+    ///
+    /// Composites such as `AllOf` rely on this agreeing with `try_acquire`:
+    /// if it returns zero, a `try_acquire` made straight afterwards from the
+    /// same thread must succeed.
+    fn time_until_ready(&self) -> time::Duration;
+}
+
+impl<L: RateLimiter + ?Sized> RateLimiter for &mut L {
+    fn try_acquire(&mut self) -> bool {
+        (**self).try_acquire()
+    }
+
+    fn acquire(&mut self) {
+        (**self).acquire()
+    }
+
+    fn acquire_n(&mut self, n: u32) {
+        (**self).acquire_n(n)
+    }
+
+    fn time_until_ready(&self) -> time::Duration {
+        (**self).time_until_ready()
+    }
+}
+
+impl<L: RateLimiter + ?Sized> RateLimiter for Box<L> {
+    fn try_acquire(&mut self) -> bool {
+        (**self).try_acquire()
+    }
+
+    fn acquire(&mut self) {
+        (**self).acquire()
+    }
+
+    fn acquire_n(&mut self, n: u32) {
+        (**self).acquire_n(n)
+    }
+
+    fn time_until_ready(&self) -> time::Duration {
+        (**self).time_until_ready()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time;
+    use {Fence, FixedWindow, Gcra, MockClock, RateLimiter, SharedFence, SlidingWindowCounter,
+         SlidingWindowLog, TokenBucket};
+
+    fn limiters(clock: &MockClock) -> Vec<Box<dyn RateLimiter>> {
+        let sec = time::Duration::from_secs(1);
+        vec![
+            Box::new(Fence::with_clock(sec, clock.clone())),
+            Box::new(SharedFence::with_clock(sec, clock.clone())),
+            Box::new(TokenBucket::with_clock(1, sec, clock.clone())),
+            Box::new(Gcra::with_clock(1, sec, clock.clone())),
+            Box::new(SlidingWindowLog::with_clock(1, sec, clock.clone())),
+            Box::new(SlidingWindowCounter::with_clock(1, sec, clock.clone())),
+            Box::new(FixedWindow::with_clock(1, sec, clock.clone())),
+        ]
+    }
+
+    #[test]
+    fn time_until_ready_agrees_with_try_acquire() {
+        let clock = MockClock::new();
+        for mut l in limiters(&clock) {
+            clock.advance(time::Duration::from_secs(1));
+            assert_eq!(l.time_until_ready(), time::Duration::from_secs(0));
+            assert!(l.try_acquire());
+            assert!(!l.try_acquire());
+
+            let wait = l.time_until_ready();
+            assert!(wait > time::Duration::from_secs(0));
+            clock.advance(wait);
+            assert!(l.try_acquire());
+        }
+    }
+
+    #[test]
+    fn acquire_n_charges_every_permit() {
+        let clock = MockClock::new();
+        for mut l in limiters(&clock) {
+            let before = clock.elapsed();
+            l.acquire_n(4);
+            l.acquire();
+            let waited = clock.elapsed() - before;
+            // Windowed limiters may admit two events either side of a
+            // boundary, so only bound the wait loosely from below.
+            assert!(waited >= time::Duration::from_secs(3));
+            assert!(waited <= time::Duration::from_secs(5));
+        }
+    }
+}
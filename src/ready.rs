@@ -0,0 +1,131 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use Fence;
+use clock::Clock;
+use timer::Timer;
+
+impl<C: Clock> Fence<C> {
+
+    /// Wait asynchronously until the fence opens, then consume the slot.
+    ///
+    /// This is the non-blocking counterpart of `sleep`: rather than parking
+    /// the thread, the returned future yields to the executor until the
+    /// deadline. It works on any executor, woken by a shared background
+    /// timer thread.
+    pub fn ready(&mut self) -> Ready<'_, C> {
+        Ready {
+            fence: self,
+            timer: Timer::new(),
+        }
+    }
+
+    /// Wait asynchronously as with `ready`, driven by tokio's timer rather
+    /// than the shared timer thread.
+    ///
+    /// The returned future must be polled within a tokio runtime that has
+    /// its time driver enabled, and panics otherwise.
+    #[cfg(feature = "tokio")]
+    pub fn ready_tokio(&mut self) -> Ready<'_, C> {
+        Ready {
+            fence: self,
+            timer: Timer::tokio(),
+        }
+    }
+}
+
+/// Future returned by `Fence::ready`.
+#[must_use = "futures do nothing unless polled"]
+pub struct Ready<'a, C: 'a> {
+    fence: &'a mut Fence<C>,
+    timer: Timer,
+}
+
+impl<'a, C: Clock> Future for Ready<'a, C> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        loop {
+            let now = this.fence.clock.now();
+            let deadline = this.fence.block_until;
+            if now >= deadline {
+                this.fence.release(now);
+                return Poll::Ready(());
+            }
+            if this.timer.poll_after(deadline.duration_since(now), cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time;
+    use std::future::Future;
+    use std::pin::Pin;
+    use {Fence, MockClock};
+    use testing::{async_poll, block_on, park_on};
+
+    #[test]
+    fn ready_waits_for_fence() {
+        let fence_dur = time::Duration::from_millis(20);
+        let mut f = Fence::from_duration(fence_dur);
+        let before = time::Instant::now();
+
+        block_on(f.ready());
+        block_on(f.ready());
+        assert!(time::Instant::now() >= before + fence_dur * 2);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn ready_tokio_waits_for_fence() {
+        let fence_dur = time::Duration::from_millis(20);
+        let mut f = Fence::from_duration(fence_dur);
+        let before = time::Instant::now();
+
+        block_on(f.ready_tokio());
+        assert!(time::Instant::now() >= before + fence_dur);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn ready_works_in_runtime_without_timers() {
+        use tokio;
+
+        let fence_dur = time::Duration::from_millis(20);
+        let mut f = Fence::from_duration(fence_dur);
+        let before = time::Instant::now();
+
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        rt.block_on(f.ready());
+        assert!(time::Instant::now() >= before + fence_dur);
+    }
+
+    #[test]
+    fn ready_works_without_runtime() {
+        let fence_dur = time::Duration::from_millis(20);
+        let mut f = Fence::from_duration(fence_dur);
+        let before = time::Instant::now();
+
+        park_on(f.ready());
+        assert!(time::Instant::now() >= before + fence_dur);
+    }
+
+    #[test]
+    fn ready_follows_fence_clock() {
+        let clock = MockClock::new();
+        let mut f = Fence::with_clock(time::Duration::from_secs(3600), clock.clone());
+
+        block_on(async_poll(|cx| {
+            let mut ready = f.ready();
+            assert!(Pin::new(&mut ready).poll(cx).is_pending());
+            clock.advance(time::Duration::from_secs(3600));
+            assert!(Pin::new(&mut ready).poll(cx).is_ready());
+        }));
+        assert!(!f.allow());
+    }
+}
@@ -0,0 +1,109 @@
+use std::time;
+
+/// A slot claimed in advance from a `Fence`.
+///
+/// The slot is charged to the fence as soon as the reservation is made, so
+/// later callers queue up behind it. The reservation does not borrow the
+/// fence, which stays free for further reservations and checks while it is
+/// outstanding. Pass it to `Fence::wait` to sleep until the slot comes up,
+/// or to `Fence::cancel` to hand the slot back if the work is abandoned.
+/// Dropping a reservation keeps the slot charged.
+#[must_use]
+#[derive(Debug, PartialEq, Eq)]
+pub struct Reservation {
+    ready_at: time::Instant,
+    previous: (time::Instant, u32),
+    next: time::Instant,
+}
+
+impl Reservation {
+
+    pub(crate) fn new(ready_at: time::Instant, previous: (time::Instant, u32),
+                      next: time::Instant) -> Reservation {
+        Reservation {
+            ready_at,
+            previous,
+            next,
+        }
+    }
+
+    /// The instant at which the reserved slot becomes valid.
+    pub fn ready_at(&self) -> time::Instant {
+        self.ready_at
+    }
+
+    /// The deadline, and the periods charged to it, that the fence held
+    /// before the reservation was made.
+    pub(crate) fn previous(&self) -> (time::Instant, u32) {
+        self.previous
+    }
+
+    /// The deadline the reservation left the fence holding.
+    pub(crate) fn next(&self) -> time::Instant {
+        self.next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time;
+    use {Clock, Fence, MockClock};
+
+    #[test]
+    fn reservation_reports_ready_instant() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        assert_eq!(f.reserve().ready_at(), start + time::Duration::from_secs(1));
+        let r = f.reserve();
+        assert_eq!(r.ready_at(), start + time::Duration::from_secs(2));
+        f.wait(r);
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(2));
+        assert!(!f.allow());
+    }
+
+    #[test]
+    fn cancelled_reservation_returns_slot() {
+        let clock = MockClock::new();
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+        clock.advance(time::Duration::from_secs(1));
+
+        let r = f.reserve();
+        assert!(f.cancel(r));
+        assert!(f.allow());
+    }
+
+    #[test]
+    fn fence_stays_usable_while_reserved() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        let first = f.reserve();
+        let second = f.reserve();
+        assert!(!f.allow());
+        assert_eq!(second.ready_at(), start + time::Duration::from_secs(2));
+
+        // Cancelling in reverse order unwinds both claims.
+        assert!(f.cancel(second));
+        assert!(f.cancel(first));
+        clock.advance(time::Duration::from_secs(1));
+        assert!(f.allow());
+    }
+
+    #[test]
+    fn cancel_after_later_claim_keeps_slot() {
+        let clock = MockClock::new();
+        let mut f = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        let first = f.reserve();
+        let _second = f.reserve();
+        assert!(!f.cancel(first));
+        clock.advance(time::Duration::from_secs(2));
+        assert!(!f.allow());
+        clock.advance(time::Duration::from_secs(1));
+        assert!(f.allow());
+        assert!(!f.allow());
+    }
+}
@@ -0,0 +1,217 @@
+use std::{thread, time};
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use clock::{Clock, MonotonicClock};
+use shared::{claim_next, nanos, try_claim};
+
+/// Automatic eviction never runs while a shard tracks fewer keys than this.
+const MIN_SWEEP: usize = 64;
+
+struct Shard<K> {
+    states: HashMap<K, AtomicU64>,
+    sweep_at: usize,
+}
+
+/// A keyed fence for many threads, split into independently locked shards.
+///
+/// Keys are hashed to one of several shards, each holding its own map from
+/// key to a `SharedFence`-style atomic deadline. Calls for a key already in
+/// the map take only a read lock on its shard and then contend on the
+/// atomic, so threads working on different keys, or even the same key,
+/// rarely block one another. A write lock is taken only to add a key or to
+/// evict idle ones.
+///
+/// As with `KeyedFence`, a fresh key is open and keys whose fence has
+/// reopened are evicted as the map grows.
+pub struct ShardedKeyedFence<K, C = MonotonicClock> {
+    clock: C,
+    origin: time::Instant,
+    duration: u64,
+    hasher: RandomState,
+    shards: Vec<RwLock<Shard<K>>>,
+}
+
+impl<K: Eq + Hash + Clone> ShardedKeyedFence<K> {
+
+    /// Construct a sharded keyed fence admitting one call per key per
+    /// `dur`, with a shard count suited to this machine.
+    pub fn new(dur: time::Duration) -> ShardedKeyedFence<K> {
+        ShardedKeyedFence::with_clock(dur, MonotonicClock)
+    }
+}
+
+impl<K: Eq + Hash + Clone, C: Clock> ShardedKeyedFence<K, C> {
+
+    /// Construct a sharded keyed fence as with `new`, reading time from
+    /// `clock`.
+    pub fn with_clock(dur: time::Duration, clock: C) -> ShardedKeyedFence<K, C> {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        ShardedKeyedFence::with_shards(dur, threads * 4, clock)
+    }
+
+    /// Construct a sharded keyed fence with exactly `shards` shards, reading
+    /// time from `clock`. A shard count of zero is treated as one.
+    pub fn with_shards(dur: time::Duration, shards: usize, clock: C) -> ShardedKeyedFence<K, C> {
+        let origin = clock.now();
+        ShardedKeyedFence {
+            clock,
+            origin,
+            duration: nanos(dur),
+            hasher: RandomState::new(),
+            shards: (0..shards.max(1)).map(|_| RwLock::new(Shard {
+                states: HashMap::new(),
+                sweep_at: MIN_SWEEP,
+            })).collect(),
+        }
+    }
+
+    /// The number of keys currently tracked.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.read().unwrap().states.len()).sum()
+    }
+
+    /// Return true if no keys are tracked.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return true and consume the slot for `key` if its fence is open,
+    /// without blocking.
+    pub fn allow(&self, key: &K) -> bool {
+        let now = self.elapsed();
+        let shard = self.shard(key);
+        if let Some(until) = shard.read().unwrap().states.get(key) {
+            return try_claim(until, now, self.duration);
+        }
+        let mut shard = shard.write().unwrap();
+        if let Some(until) = shard.states.get(key) {
+            return try_claim(until, now, self.duration);
+        }
+        self.insert(&mut shard, key, now);
+        true
+    }
+
+    /// Sleep the current thread until this caller's turn for `key` comes
+    /// up.
+    ///
+    /// As with `SharedFence::sleep`, each caller claims the next free slot
+    /// for the key before sleeping, and no lock is held while it sleeps.
+    pub fn sleep(&self, key: &K) {
+        let now = self.elapsed();
+        let prev = self.claim(key, now);
+        if now < prev {
+            self.clock.sleep(time::Duration::from_nanos(prev - now));
+        }
+    }
+
+    /// Drop every key whose fence has reopened, returning how many were
+    /// dropped.
+    pub fn evict(&self) -> usize {
+        let now = self.elapsed();
+        self.shards.iter()
+            .map(|s| sweep(&mut s.write().unwrap(), now))
+            .sum()
+    }
+
+    fn claim(&self, key: &K, now: u64) -> u64 {
+        let shard = self.shard(key);
+        if let Some(until) = shard.read().unwrap().states.get(key) {
+            return claim_next(until, now, self.duration);
+        }
+        let mut shard = shard.write().unwrap();
+        if let Some(until) = shard.states.get(key) {
+            return claim_next(until, now, self.duration);
+        }
+        self.insert(&mut shard, key, now);
+        now
+    }
+
+    fn insert(&self, shard: &mut Shard<K>, key: &K, now: u64) {
+        shard.states.insert(key.clone(), AtomicU64::new(now.saturating_add(self.duration)));
+        if shard.states.len() >= shard.sweep_at {
+            sweep(shard, now);
+            shard.sweep_at = MIN_SWEEP.max(shard.states.len() * 2);
+        }
+    }
+
+    fn shard(&self, key: &K) -> &RwLock<Shard<K>> {
+        let hash = self.hasher.hash_one(key);
+        &self.shards[(hash % self.shards.len() as u64) as usize]
+    }
+
+    fn elapsed(&self) -> u64 {
+        nanos(self.clock.now().saturating_duration_since(self.origin))
+    }
+}
+
+fn sweep<K>(shard: &mut Shard<K>, now: u64) -> usize {
+    let before = shard.states.len();
+    shard.states.retain(|_, until| now < until.load(Ordering::Acquire));
+    before - shard.states.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use {MockClock, ShardedKeyedFence};
+
+    #[test]
+    fn keys_are_limited_independently() {
+        let clock = MockClock::new();
+        let f = ShardedKeyedFence::with_shards(time::Duration::from_secs(1), 4, clock.clone());
+
+        assert!(f.allow(&"alice"));
+        assert!(!f.allow(&"alice"));
+        assert!(f.allow(&"bob"));
+
+        clock.advance(time::Duration::from_secs(1));
+        assert!(f.allow(&"alice"));
+        f.sleep(&"alice");
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn one_admission_per_key_across_threads() {
+        let clock = MockClock::new();
+        let f = Arc::new(ShardedKeyedFence::with_shards(time::Duration::from_secs(1), 8, clock));
+        let admitted = Arc::new(AtomicUsize::new(0));
+
+        let workers: Vec<_> = (0..8).map(|_| {
+            let f = f.clone();
+            let admitted = admitted.clone();
+            thread::spawn(move || {
+                for key in 0..1000u32 {
+                    if f.allow(&key) {
+                        admitted.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+            })
+        }).collect();
+        for w in workers {
+            w.join().unwrap();
+        }
+        assert_eq!(admitted.load(Ordering::SeqCst), 1000);
+        assert_eq!(f.len(), 1000);
+    }
+
+    #[test]
+    fn idle_keys_are_evicted() {
+        let clock = MockClock::new();
+        let f = ShardedKeyedFence::with_shards(time::Duration::from_millis(10), 4, clock.clone());
+
+        for key in 0..100_000u32 {
+            f.allow(&key);
+            clock.advance(time::Duration::from_millis(1));
+        }
+        assert!(f.len() < 4 * 200);
+        clock.advance(time::Duration::from_millis(10));
+        f.evict();
+        assert!(f.is_empty());
+    }
+}
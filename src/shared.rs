@@ -0,0 +1,269 @@
+use std::time;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use cancel::CancelToken;
+use clock::{Clock, MonotonicClock};
+use error::Cancelled;
+use limiter::RateLimiter;
+
+/// A fence that can be shared between threads.
+///
+/// Unlike `Fence`, both `allow` and `sleep` take `&self`, so a single
+/// `SharedFence` can be placed in an `Arc` and polled from many workers
+/// without a `Mutex`. The next permitted instant is kept in an atomic as
+/// nanoseconds past the fence's construction, and threads contend for it
+/// with compare-and-swap.
+pub struct SharedFence<C = MonotonicClock> {
+    clock: C,
+    origin: time::Instant,
+    duration: AtomicU64,
+    block_until: AtomicU64,
+}
+
+impl SharedFence {
+
+    /// Construct a shared fence from the specified seconds.
+    pub fn from_secs(s: u64) -> SharedFence {
+        SharedFence::from_duration(time::Duration::from_secs(s))
+    }
+
+    /// Construct a shared fence from the specified milliseconds.
+    pub fn from_millis(m: u64) -> SharedFence {
+        SharedFence::from_duration(time::Duration::from_millis(m))
+    }
+
+    /// Construct a shared fence from the given duration.
+    pub fn from_duration(dur: time::Duration) -> SharedFence {
+        SharedFence::with_clock(dur, MonotonicClock)
+    }
+}
+
+impl<C: Clock> SharedFence<C> {
+
+    /// Construct a shared fence from the given duration, reading time from
+    /// `clock`.
+    pub fn with_clock(dur: time::Duration, clock: C) -> SharedFence<C> {
+        let origin = clock.now();
+        let duration = nanos(dur);
+        SharedFence {
+            clock,
+            origin,
+            duration: AtomicU64::new(duration),
+            block_until: AtomicU64::new(duration),
+        }
+    }
+
+    /// The current period between slots.
+    pub fn duration(&self) -> time::Duration {
+        time::Duration::from_nanos(self.duration.load(Ordering::Acquire))
+    }
+
+    /// Change the period between slots, safely with respect to concurrent
+    /// callers.
+    ///
+    /// The pending deadline is moved as for `Fence::set_duration`, so the
+    /// most recently claimed slot is followed by the new period.
+    pub fn set_duration(&self, dur: time::Duration) {
+        let new = nanos(dur);
+        let old = self.duration.swap(new, Ordering::AcqRel);
+        let _ = self.block_until.fetch_update(Ordering::AcqRel, Ordering::Acquire, |until| {
+            Some(until.saturating_sub(old).saturating_add(new))
+        });
+    }
+
+    /// Sleep the current thread until this caller's turn comes up.
+    ///
+    /// Each caller claims the next free slot before sleeping, so concurrent
+    /// sleepers are released one `duration` apart rather than all at once.
+    pub fn sleep(&self) {
+        let now = self.elapsed();
+        let dur = self.duration.load(Ordering::Acquire);
+        let prev = claim_next(&self.block_until, now, dur);
+        if now < prev {
+            self.clock.sleep(time::Duration::from_nanos(prev - now));
+        }
+    }
+
+    /// Sleep as with `sleep`, but return early if `token` is cancelled.
+    ///
+    /// A cancelled caller hands its slot back unless another caller has
+    /// already queued behind it.
+    pub fn sleep_cancellable(&self, token: &CancelToken) -> Result<(), Cancelled> {
+        if token.is_cancelled() {
+            return Err(Cancelled);
+        }
+        let now = self.elapsed();
+        let dur = self.duration.load(Ordering::Acquire);
+        let prev = claim_next(&self.block_until, now, dur);
+        if now < prev {
+            let wait = time::Duration::from_nanos(prev - now);
+            if let Err(cancelled) = self.clock.sleep_cancellable(wait, token) {
+                let claimed = prev.saturating_add(dur);
+                let _ = self.block_until.compare_exchange(claimed, prev, Ordering::AcqRel,
+                                                          Ordering::Acquire);
+                return Err(cancelled);
+            }
+        }
+        Ok(())
+    }
+
+    /// Return true and consume the slot if the fence is open, without
+    /// blocking.
+    pub fn allow(&self) -> bool {
+        let now = self.elapsed();
+        let dur = self.duration.load(Ordering::Acquire);
+        try_claim(&self.block_until, now, dur)
+    }
+
+    /// How long until `allow` would succeed, or zero if it would succeed
+    /// now.
+    pub fn time_until_ready(&self) -> time::Duration {
+        let until = self.block_until.load(Ordering::Acquire);
+        time::Duration::from_nanos(until.saturating_sub(self.elapsed()))
+    }
+
+    fn elapsed(&self) -> u64 {
+        nanos(self.clock.now().duration_since(self.origin))
+    }
+}
+
+impl<C: Clock> RateLimiter for SharedFence<C> {
+    fn try_acquire(&mut self) -> bool {
+        self.allow()
+    }
+
+    fn acquire(&mut self) {
+        self.sleep()
+    }
+
+    fn time_until_ready(&self) -> time::Duration {
+        SharedFence::time_until_ready(self)
+    }
+}
+
+/// Claim the slot held in `block_until` if it is open at `now`, closing it
+/// for `dur`. Times are nanoseconds past some fixed origin.
+pub(crate) fn try_claim(block_until: &AtomicU64, now: u64, dur: u64) -> bool {
+    block_until
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |until| {
+            if now < until {
+                None
+            } else {
+                Some(now.saturating_add(dur))
+            }
+        })
+        .is_ok()
+}
+
+/// Claim the next free slot in `block_until`, whether or not it is open at
+/// `now`, and return the previous deadline. The claimed slot opens at the
+/// later of that deadline and `now`.
+pub(crate) fn claim_next(block_until: &AtomicU64, now: u64, dur: u64) -> u64 {
+    block_until
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire,
+                      |until| Some(until.max(now).saturating_add(dur)))
+        .unwrap()
+}
+
+/// Convert a duration to whole nanoseconds, saturating at `u64::MAX`.
+pub(crate) fn nanos(dur: time::Duration) -> u64 {
+    let n = dur.as_nanos();
+    if n > u64::MAX as u128 {
+        u64::MAX
+    } else {
+        n as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use {CancelToken, Cancelled, Fence, MockClock, SharedFence};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn fences_are_send_and_sync() {
+        assert_send_sync::<Fence>();
+        assert_send_sync::<SharedFence>();
+        assert_send_sync::<SharedFence<MockClock>>();
+    }
+
+    #[test]
+    fn shared_fence_allow() {
+        let clock = MockClock::new();
+        let f = SharedFence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        assert!(!f.allow());
+        clock.advance(time::Duration::from_secs(1));
+        assert!(f.allow());
+        assert!(!f.allow());
+    }
+
+    #[test]
+    fn shared_fence_sleepers_take_turns() {
+        let clock = MockClock::new();
+        let f = SharedFence::with_clock(time::Duration::from_secs(1), clock.clone());
+
+        f.sleep();
+        f.sleep();
+        f.sleep();
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(3));
+    }
+
+    #[test]
+    fn shared_fence_admits_one_per_period_across_threads() {
+        let clock = MockClock::new();
+        let f = Arc::new(SharedFence::with_clock(time::Duration::from_secs(1), clock.clone()));
+        clock.advance(time::Duration::from_secs(1));
+
+        let admitted = Arc::new(AtomicUsize::new(0));
+        let workers: Vec<_> = (0..8).map(|_| {
+            let f = f.clone();
+            let admitted = admitted.clone();
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    if f.allow() {
+                        admitted.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+            })
+        }).collect();
+        for w in workers {
+            w.join().unwrap();
+        }
+        assert_eq!(admitted.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn cancelled_sleeper_returns_slot() {
+        let f = Arc::new(SharedFence::from_secs(60));
+        let token = CancelToken::new();
+        let canceller = token.clone();
+
+        let t = thread::spawn(move || {
+            thread::sleep(time::Duration::from_millis(10));
+            canceller.cancel();
+        });
+        assert_eq!(f.sleep_cancellable(&token), Err(Cancelled));
+        t.join().unwrap();
+        assert!(f.time_until_ready() <= time::Duration::from_secs(60));
+    }
+
+    #[test]
+    fn set_duration_moves_pending_deadline() {
+        let clock = MockClock::new();
+        let f = SharedFence::with_clock(time::Duration::from_secs(10), clock.clone());
+
+        f.set_duration(time::Duration::from_secs(2));
+        assert_eq!(f.duration(), time::Duration::from_secs(2));
+        assert_eq!(f.time_until_ready(), time::Duration::from_secs(2));
+
+        clock.advance(time::Duration::from_secs(2));
+        assert!(f.allow());
+        f.set_duration(time::Duration::from_secs(5));
+        assert_eq!(f.time_until_ready(), time::Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,198 @@
+use std::time;
+
+use clock::{Clock, MonotonicClock};
+use limiter::RateLimiter;
+
+/// Upper bounds of the wait-time histogram buckets.
+///
+/// A wait falls in the first bucket whose bound it does not exceed. Waits
+/// longer than the last bound are counted in one final overflow bucket, so
+/// `Stats::wait_histogram` has one more entry than this array.
+pub const WAIT_BUCKETS: [time::Duration; 7] = [
+    time::Duration::from_secs(0),
+    time::Duration::from_micros(100),
+    time::Duration::from_millis(1),
+    time::Duration::from_millis(10),
+    time::Duration::from_millis(100),
+    time::Duration::from_secs(1),
+    time::Duration::from_secs(10),
+];
+
+/// A snapshot of the statistics recorded by an `Instrumented` limiter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Calls that were granted, whether immediately or after blocking.
+    pub allowed: u64,
+    /// Non-blocking calls that were refused.
+    pub denied: u64,
+    /// Total time spent blocked in blocking calls.
+    pub blocked_total: time::Duration,
+    /// Longest single time spent blocked in a blocking call.
+    pub blocked_max: time::Duration,
+    /// Counts of blocking calls by time spent blocked, bucketed by
+    /// `WAIT_BUCKETS`.
+    pub wait_histogram: [u64; 8],
+}
+
+impl Stats {
+    fn record_wait(&mut self, waited: time::Duration) {
+        self.allowed += 1;
+        self.blocked_total += waited;
+        self.blocked_max = self.blocked_max.max(waited);
+        let bucket = WAIT_BUCKETS.iter()
+            .position(|&bound| waited <= bound)
+            .unwrap_or(WAIT_BUCKETS.len());
+        self.wait_histogram[bucket] += 1;
+    }
+}
+
+/// A limiter wrapper that records how often callers are allowed, denied and
+/// made to wait.
+///
+/// Recording is opt-in: wrap any limiter to start collecting, and read the
+/// counters back with `stats`.
+pub struct Instrumented<L, C = MonotonicClock> {
+    limiter: L,
+    clock: C,
+    stats: Stats,
+}
+
+impl<L: RateLimiter> Instrumented<L> {
+
+    /// Wrap `limiter`, timing blocking calls with the monotonic clock.
+    pub fn new(limiter: L) -> Instrumented<L> {
+        Instrumented::with_clock(limiter, MonotonicClock)
+    }
+}
+
+impl<L: RateLimiter, C: Clock> Instrumented<L, C> {
+
+    /// Wrap `limiter`, timing blocking calls with `clock`.
+    pub fn with_clock(limiter: L, clock: C) -> Instrumented<L, C> {
+        Instrumented {
+            limiter,
+            clock,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Sleep until the wrapped limiter grants a permit, recording the wait.
+    pub fn sleep(&mut self) {
+        self.acquire()
+    }
+
+    /// Ask the wrapped limiter for a permit without blocking, recording the
+    /// outcome.
+    pub fn allow(&mut self) -> bool {
+        self.try_acquire()
+    }
+
+    /// The statistics recorded so far.
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Clear the recorded statistics.
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+
+    /// Borrow the wrapped limiter.
+    pub fn get_ref(&self) -> &L {
+        &self.limiter
+    }
+
+    /// Mutably borrow the wrapped limiter. Calls made through it are not
+    /// recorded.
+    pub fn get_mut(&mut self) -> &mut L {
+        &mut self.limiter
+    }
+
+    /// Unwrap the limiter, discarding the statistics.
+    pub fn into_inner(self) -> L {
+        self.limiter
+    }
+}
+
+impl<L: RateLimiter, C: Clock> RateLimiter for Instrumented<L, C> {
+    fn try_acquire(&mut self) -> bool {
+        let allowed = self.limiter.try_acquire();
+        if allowed {
+            self.stats.allowed += 1;
+        } else {
+            self.stats.denied += 1;
+        }
+        allowed
+    }
+
+    fn acquire(&mut self) {
+        let start = self.clock.now();
+        self.limiter.acquire();
+        let waited = self.clock.now().saturating_duration_since(start);
+        self.stats.record_wait(waited);
+    }
+
+    fn acquire_n(&mut self, n: u32) {
+        let start = self.clock.now();
+        self.limiter.acquire_n(n);
+        let waited = self.clock.now().saturating_duration_since(start);
+        self.stats.record_wait(waited);
+    }
+
+    fn time_until_ready(&self) -> time::Duration {
+        self.limiter.time_until_ready()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time;
+    use {Fence, Instrumented, MockClock, Stats, TokenBucket};
+
+    #[test]
+    fn counts_allowed_and_denied() {
+        let clock = MockClock::new();
+        let bucket = TokenBucket::with_clock(2, time::Duration::from_secs(1), clock.clone());
+        let mut l = Instrumented::with_clock(bucket, clock.clone());
+
+        for _ in 0..5 {
+            l.allow();
+        }
+        let stats = l.stats();
+        assert_eq!(stats.allowed, 2);
+        assert_eq!(stats.denied, 3);
+    }
+
+    #[test]
+    fn records_wait_times() {
+        let clock = MockClock::new();
+        let fence = Fence::with_clock(time::Duration::from_millis(50), clock.clone());
+        let mut l = Instrumented::with_clock(fence, clock.clone());
+
+        l.sleep();
+        clock.advance(time::Duration::from_millis(50));
+        l.sleep();
+        clock.advance(time::Duration::from_millis(45));
+        l.sleep();
+
+        let stats = l.stats();
+        assert_eq!(stats.allowed, 3);
+        assert_eq!(stats.blocked_total, time::Duration::from_millis(55));
+        assert_eq!(stats.blocked_max, time::Duration::from_millis(50));
+        assert_eq!(stats.wait_histogram, [1, 0, 0, 1, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn reset_clears_stats() {
+        let clock = MockClock::new();
+        let fence = Fence::with_clock(time::Duration::from_secs(20), clock.clone());
+        let mut l = Instrumented::with_clock(fence, clock.clone());
+
+        l.sleep();
+        assert!(!l.allow());
+        assert_eq!(l.stats().wait_histogram[7], 1);
+
+        l.reset_stats();
+        assert_eq!(l.stats(), Stats::default());
+    }
+}
@@ -0,0 +1,297 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+
+use limiter::RateLimiter;
+use timer::Timer;
+
+/// The most items taken from the inner stream in one `poll_next` before
+/// yielding back to the executor.
+const POLL_BUDGET: usize = 32;
+
+/// What a rate-limited stream does with items that arrive faster than the
+/// limiter permits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Hold each item until the limiter permits it. The inner stream is not
+    /// polled again until the held item is delivered, so no item is lost
+    /// and backpressure reaches the producer.
+    Delay,
+    /// Discard any item that arrives while the limiter is closed.
+    Drop,
+    /// Hold only the most recent item, replacing it as newer ones arrive,
+    /// and deliver it once the limiter permits.
+    KeepLatest,
+}
+
+/// Extension methods for pacing a stream with a limiter.
+pub trait RateLimitedStreamExt: Stream + Sized {
+    /// Deliver items no faster than `limiter` permits, handling items that
+    /// arrive too quickly according to `overflow`.
+    ///
+    /// With `Drop` and `KeepLatest` the inner stream is drained even while
+    /// the limiter is closed, yielding to the executor after every few
+    /// items so that a stream which is always ready cannot monopolize it.
+    ///
+    /// The stream must be `Unpin`; use `Box::pin` on one that is not.
+    fn rate_limited<L: RateLimiter>(self, limiter: L, overflow: Overflow) -> RateLimited<Self, L> {
+        RateLimited {
+            stream: self,
+            limiter,
+            overflow,
+            pending: None,
+            done: false,
+            timer: Timer::new(),
+        }
+    }
+}
+
+impl<S: Stream> RateLimitedStreamExt for S {}
+
+/// Stream returned by `RateLimitedStreamExt::rate_limited`.
+#[must_use = "streams do nothing unless polled"]
+pub struct RateLimited<S: Stream, L> {
+    stream: S,
+    limiter: L,
+    overflow: Overflow,
+    pending: Option<S::Item>,
+    done: bool,
+    timer: Timer,
+}
+
+// Nothing is ever pinned through a `RateLimited`; the inner stream is only
+// polled via `Pin::new`, which already requires it to be `Unpin`.
+impl<S: Stream + Unpin, L> Unpin for RateLimited<S, L> {}
+
+impl<S: Stream, L> RateLimited<S, L> {
+
+    /// Unwrap the stream and limiter, discarding any held item.
+    pub fn into_inner(self) -> (S, L) {
+        (self.stream, self.limiter)
+    }
+}
+
+impl<S: Stream + Unpin, L: RateLimiter> Stream for RateLimited<S, L> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<S::Item>> {
+        let this = self.get_mut();
+        loop {
+            let mut budget = POLL_BUDGET;
+            while !this.done && (this.pending.is_none() || this.overflow != Overflow::Delay) {
+                if budget == 0 {
+                    // A stream that is always ready would keep us here
+                    // forever, so deliver what is held if we may, and
+                    // otherwise ask to be polled again once others have run.
+                    if this.pending.is_some() && this.limiter.try_acquire() {
+                        return Poll::Ready(this.pending.take());
+                    }
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                budget -= 1;
+                match Pin::new(&mut this.stream).poll_next(cx) {
+                    Poll::Ready(Some(item)) => {
+                        if this.overflow != Overflow::Drop {
+                            this.pending = Some(item);
+                        } else if this.limiter.try_acquire() {
+                            return Poll::Ready(Some(item));
+                        }
+                    }
+                    Poll::Ready(None) => this.done = true,
+                    Poll::Pending => break,
+                }
+            }
+            if this.pending.is_none() {
+                return if this.done { Poll::Ready(None) } else { Poll::Pending };
+            }
+            if this.limiter.try_acquire() {
+                return Poll::Ready(this.pending.take());
+            }
+            if this.timer.poll_after(this.limiter.time_until_ready(), cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time;
+    use std::mem;
+    use std::collections::VecDeque;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll, Wake, Waker};
+
+    use futures_core::Stream;
+
+    use {Fence, MockClock};
+    use stream::{Overflow, RateLimitedStreamExt};
+    use testing::{async_poll, block_on};
+
+    // A stream fed by hand: `Some` entries are items, `None` ends it, and
+    // an empty queue is pending.
+    #[derive(Clone, Default)]
+    struct Feed(Arc<Mutex<VecDeque<Option<u32>>>>);
+
+    impl Feed {
+        fn send(&self, items: &[u32]) {
+            self.0.lock().unwrap().extend(items.iter().map(|&i| Some(i)));
+        }
+
+        fn close(&self) {
+            self.0.lock().unwrap().push_back(None);
+        }
+    }
+
+    impl Stream for Feed {
+        type Item = u32;
+
+        fn poll_next(self: Pin<&mut Self>, _: &mut Context) -> Poll<Option<u32>> {
+            match self.0.lock().unwrap().pop_front() {
+                Some(item) => Poll::Ready(item),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    // A stream that is always ready with the next count.
+    struct Counter(u32);
+
+    impl Stream for Counter {
+        type Item = u32;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<Option<u32>> {
+            self.0 += 1;
+            Poll::Ready(Some(self.0))
+        }
+    }
+
+    #[derive(Default)]
+    struct Wakes(AtomicUsize);
+
+    impl Wake for Wakes {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn next<S: Stream + Unpin>(s: &mut S, cx: &mut Context) -> Poll<Option<S::Item>> {
+        Pin::new(s).poll_next(cx)
+    }
+
+    #[test]
+    fn delay_holds_items() {
+        let clock = MockClock::new();
+        let fence = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+        let feed = Feed::default();
+        let mut s = feed.clone().rate_limited(fence, Overflow::Delay);
+
+        block_on(async_poll(|cx| {
+            feed.send(&[0, 1]);
+            feed.close();
+            assert_eq!(next(&mut s, cx), Poll::Pending);
+            clock.advance(time::Duration::from_secs(1));
+            assert_eq!(next(&mut s, cx), Poll::Ready(Some(0)));
+            assert_eq!(next(&mut s, cx), Poll::Pending);
+            clock.advance(time::Duration::from_secs(1));
+            assert_eq!(next(&mut s, cx), Poll::Ready(Some(1)));
+            assert_eq!(next(&mut s, cx), Poll::Ready(None));
+        }));
+    }
+
+    #[test]
+    fn drop_discards_early_items() {
+        let clock = MockClock::new();
+        let fence = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+        let feed = Feed::default();
+        let mut s = feed.clone().rate_limited(fence, Overflow::Drop);
+
+        block_on(async_poll(|cx| {
+            feed.send(&[0, 1]);
+            assert_eq!(next(&mut s, cx), Poll::Pending);
+            clock.advance(time::Duration::from_secs(1));
+            feed.send(&[2, 3]);
+            feed.close();
+            assert_eq!(next(&mut s, cx), Poll::Ready(Some(2)));
+            assert_eq!(next(&mut s, cx), Poll::Ready(None));
+        }));
+    }
+
+    #[test]
+    fn keep_latest_replaces_held_item() {
+        let clock = MockClock::new();
+        let fence = Fence::with_clock(time::Duration::from_secs(1), clock.clone());
+        let feed = Feed::default();
+        let mut s = feed.clone().rate_limited(fence, Overflow::KeepLatest);
+
+        block_on(async_poll(|cx| {
+            feed.send(&[0, 1, 2]);
+            assert_eq!(next(&mut s, cx), Poll::Pending);
+            clock.advance(time::Duration::from_secs(1));
+            assert_eq!(next(&mut s, cx), Poll::Ready(Some(2)));
+            feed.send(&[3]);
+            feed.close();
+            assert_eq!(next(&mut s, cx), Poll::Pending);
+            clock.advance(time::Duration::from_secs(1));
+            assert_eq!(next(&mut s, cx), Poll::Ready(Some(3)));
+            assert_eq!(next(&mut s, cx), Poll::Ready(None));
+        }));
+    }
+
+    #[test]
+    fn always_ready_stream_yields() {
+        for &overflow in &[Overflow::Drop, Overflow::KeepLatest] {
+            let clock = MockClock::new();
+            let fence = Fence::with_clock(time::Duration::from_secs(3600), clock.clone());
+            let mut s = Counter(0).rate_limited(fence, overflow);
+            let wakes = Arc::new(Wakes::default());
+            let waker = Waker::from(wakes.clone());
+            let mut cx = Context::from_waker(&waker);
+
+            assert_eq!(next(&mut s, &mut cx), Poll::Pending);
+            assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+            clock.advance(time::Duration::from_secs(3600));
+            match next(&mut s, &mut cx) {
+                Poll::Ready(Some(n)) => assert!(n > 1),
+                other => panic!("expected an item, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn delay_wakes_on_time() {
+        let fence_dur = time::Duration::from_millis(20);
+        let feed = Feed::default();
+        feed.send(&[0, 1]);
+        feed.close();
+        let mut s = feed.rate_limited(Fence::from_duration(fence_dur), Overflow::Delay);
+        let before = time::Instant::now();
+
+        let items = block_on(Collect(&mut s, Vec::new()));
+        assert_eq!(items, vec![0, 1]);
+        assert!(time::Instant::now() >= before + fence_dur * 2);
+    }
+
+    // Resolves to every item of a stream once it ends.
+    struct Collect<'a, S: 'a + Stream>(&'a mut S, Vec<S::Item>);
+
+    impl<'a, S: Stream + Unpin> Future for Collect<'a, S> where S::Item: Unpin {
+        type Output = Vec<S::Item>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Vec<S::Item>> {
+            let this = self.get_mut();
+            loop {
+                match next(this.0, cx) {
+                    Poll::Ready(Some(item)) => this.1.push(item),
+                    Poll::Ready(None) => return Poll::Ready(mem::take(&mut this.1)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+    }
+}
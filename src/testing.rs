@@ -0,0 +1,63 @@
+//! Helpers for driving futures in tests.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// Polls `f` on the current thread, parking it between wakeups, with no
+// runtime involved.
+pub fn park_on<F: Future>(f: F) -> F::Output {
+    use std::sync::Arc;
+    use std::task::{Wake, Waker};
+    use std::thread;
+
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark()
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut f = Box::pin(f);
+    loop {
+        match f.as_mut().poll(&mut cx) {
+            Poll::Ready(v) => return v,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(not(feature = "tokio"))]
+pub fn block_on<F: Future>(f: F) -> F::Output {
+    park_on(f)
+}
+
+#[cfg(feature = "tokio")]
+pub fn block_on<F: Future>(f: F) -> F::Output {
+    use tokio;
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(f)
+}
+
+// Runs `f` once from inside a poll, so that runtime-backed timers have
+// the context they need.
+pub fn async_poll<F: FnOnce(&mut Context) + Unpin>(f: F) -> impl Future<Output = ()> {
+    struct Once<F>(Option<F>);
+
+    impl<F: FnOnce(&mut Context) + Unpin> Future for Once<F> {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            (self.0.take().unwrap())(cx);
+            Poll::Ready(())
+        }
+    }
+
+    Once(Some(f))
+}
@@ -0,0 +1,296 @@
+use std::{io, time};
+
+use bucket::TokenBucket;
+use clock::{Clock, MonotonicClock};
+use limiter::RateLimiter;
+
+/// The shortest refill interval a byte budget uses, in nanoseconds. Whole
+/// nanosecond intervals are too coarse to express high rates, so fast
+/// budgets refill several bytes at a time instead.
+const MIN_REFILL_NANOS: u64 = 1000;
+
+/// Bytes spent against a token bucket in which each token is worth `unit`
+/// bytes, with bytes left over from a partly spent token held as credit.
+struct ByteBudget<C> {
+    bucket: TokenBucket<C>,
+    unit: u32,
+    credit: u32,
+}
+
+impl<C: Clock> ByteBudget<C> {
+
+    fn new(bytes_per_sec: u32, burst: u32, clock: C) -> ByteBudget<C> {
+        assert!(bytes_per_sec > 0, "bytes_per_sec must be positive");
+        let rate = bytes_per_sec as u64;
+        let unit = (rate * MIN_REFILL_NANOS).div_ceil(1_000_000_000);
+        // Round the interval up so the rate is never exceeded.
+        let refill = time::Duration::from_nanos((unit * 1_000_000_000).div_ceil(rate));
+        let unit = unit as u32;
+        ByteBudget {
+            bucket: TokenBucket::with_clock(burst / unit, refill, clock),
+            unit,
+            credit: 0,
+        }
+    }
+
+    /// The most bytes that can be spent at once.
+    fn max_chunk(&self) -> usize {
+        self.bucket.capacity() as usize * self.unit as usize
+    }
+
+    /// Sleep until `n` bytes may pass, then spend them. `n` must not
+    /// exceed `max_chunk`.
+    fn spend(&mut self, n: u32) {
+        if n <= self.credit {
+            self.credit -= n;
+            return;
+        }
+        let short = n - self.credit;
+        let tokens = short.div_ceil(self.unit);
+        self.bucket.acquire_n(tokens);
+        self.credit = tokens * self.unit - short;
+    }
+
+    /// Return `n` unused bytes.
+    fn refund(&mut self, n: u32) {
+        let credit = self.credit as u64 + n as u64;
+        self.bucket.refund((credit / self.unit as u64) as u32);
+        self.credit = (credit % self.unit as u64) as u32;
+    }
+}
+
+/// A reader limited to a number of bytes per second.
+///
+/// Bytes are paid for from a `TokenBucket` holding `burst` bytes, so after a
+/// quiet spell up to `burst` bytes pass straight through. Reads are
+/// capped at `burst` bytes and paid for once the inner read returns, so a
+/// slow source is never kept waiting for bytes it has not produced.
+pub struct ThrottledReader<R, C = MonotonicClock> {
+    inner: R,
+    budget: ByteBudget<C>,
+}
+
+impl<R: io::Read> ThrottledReader<R> {
+
+    /// Wrap `inner`, limiting it to `bytes_per_sec` with bursts of up to
+    /// `burst` bytes. Above a million bytes per second, bytes are metered
+    /// in units of `bytes_per_sec / 1_000_000` rounded up, and the burst is
+    /// rounded down to whole units, but never below one unit.
+    ///
+    /// Panics if `bytes_per_sec` is zero.
+    pub fn new(inner: R, bytes_per_sec: u32, burst: u32) -> ThrottledReader<R> {
+        ThrottledReader::with_clock(inner, bytes_per_sec, burst, MonotonicClock)
+    }
+}
+
+impl<R: io::Read, C: Clock> ThrottledReader<R, C> {
+
+    /// Wrap `inner` as with `new`, reading time from `clock`.
+    pub fn with_clock(inner: R, bytes_per_sec: u32, burst: u32, clock: C) -> ThrottledReader<R, C> {
+        ThrottledReader {
+            inner,
+            budget: ByteBudget::new(bytes_per_sec, burst, clock),
+        }
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader. Bytes read through it
+    /// are not counted.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwrap the reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: io::Read, C: Clock> io::Read for ThrottledReader<R, C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.budget.max_chunk());
+        let n = self.inner.read(&mut buf[..len])?;
+        self.budget.spend(n as u32);
+        Ok(n)
+    }
+}
+
+/// A writer limited to a number of bytes per second.
+///
+/// Bytes are paid for from a `TokenBucket` holding `burst` bytes, so after a
+/// quiet spell up to `burst` bytes pass straight through. Writes are
+/// capped at `burst` bytes and paid for before they reach the inner writer;
+/// tokens for bytes it does not accept are refunded.
+pub struct ThrottledWriter<W, C = MonotonicClock> {
+    inner: W,
+    budget: ByteBudget<C>,
+}
+
+impl<W: io::Write> ThrottledWriter<W> {
+
+    /// Wrap `inner`, limiting it to `bytes_per_sec` with bursts of up to
+    /// `burst` bytes. Above a million bytes per second, bytes are metered
+    /// in units of `bytes_per_sec / 1_000_000` rounded up, and the burst is
+    /// rounded down to whole units, but never below one unit.
+    ///
+    /// Panics if `bytes_per_sec` is zero.
+    pub fn new(inner: W, bytes_per_sec: u32, burst: u32) -> ThrottledWriter<W> {
+        ThrottledWriter::with_clock(inner, bytes_per_sec, burst, MonotonicClock)
+    }
+}
+
+impl<W: io::Write, C: Clock> ThrottledWriter<W, C> {
+
+    /// Wrap `inner` as with `new`, reading time from `clock`.
+    pub fn with_clock(inner: W, bytes_per_sec: u32, burst: u32, clock: C) -> ThrottledWriter<W, C> {
+        ThrottledWriter {
+            inner,
+            budget: ByteBudget::new(bytes_per_sec, burst, clock),
+        }
+    }
+
+    /// Get a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped writer. Bytes written through
+    /// it are not counted.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Unwrap the writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: io::Write, C: Clock> io::Write for ThrottledWriter<W, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.budget.max_chunk());
+        self.budget.spend(len as u32);
+        match self.inner.write(&buf[..len]) {
+            Ok(n) => {
+                self.budget.refund((len - n) as u32);
+                Ok(n)
+            }
+            Err(e) => {
+                self.budget.refund(len as u32);
+                Err(e)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, thread, time};
+    use std::io::{Read, Write};
+    use {MockClock, ThrottledReader, ThrottledWriter};
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn reader_limits_bytes() {
+        let clock = MockClock::new();
+        let src = data(10_000);
+        let mut r = ThrottledReader::with_clock(&src[..], 1000, 1000, clock.clone());
+
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out, src);
+        // The first 1000 bytes are a free burst.
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(9));
+    }
+
+    #[test]
+    fn writer_limits_bytes() {
+        let clock = MockClock::new();
+        let src = data(10_000);
+        let mut w = ThrottledWriter::with_clock(Vec::new(), 1000, 1000, clock.clone());
+
+        w.write_all(&src).unwrap();
+        assert_eq!(w.into_inner(), src);
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(9));
+    }
+
+    #[test]
+    fn large_writes_are_split() {
+        let clock = MockClock::new();
+        let mut w = ThrottledWriter::with_clock(Vec::new(), 1000, 100, clock.clone());
+
+        assert_eq!(w.write(&data(5000)).unwrap(), 100);
+        assert_eq!(w.get_ref().len(), 100);
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(0));
+    }
+
+    #[test]
+    fn short_writes_are_refunded() {
+        let clock = MockClock::new();
+        let mut buf = [0u8; 50];
+        let mut w = ThrottledWriter::with_clock(&mut buf[..], 1000, 100, clock.clone());
+
+        assert_eq!(w.write(&data(100)).unwrap(), 50);
+        assert_eq!(w.write(&data(50)).unwrap(), 0);
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(0));
+    }
+
+    #[test]
+    fn high_rates_are_not_exceeded() {
+        for &rate in &[300_000_000u32, 2_000_000_000] {
+            let clock = MockClock::new();
+            let chunk = vec![0u8; 1_000_000];
+            let mut w = ThrottledWriter::with_clock(io::sink(), rate, 1_000_000, clock.clone());
+
+            // One second's worth after the initial burst.
+            for _ in 0..(rate / 1_000_000 + 1) {
+                w.write_all(&chunk).unwrap();
+            }
+            let elapsed = clock.elapsed();
+            assert!(elapsed >= time::Duration::from_secs(1), "{} B/s took {:?}", rate, elapsed);
+            assert!(elapsed <= time::Duration::from_millis(1001), "{} B/s took {:?}", rate, elapsed);
+        }
+    }
+
+    #[test]
+    fn high_rate_reader_is_not_exceeded() {
+        let clock = MockClock::new();
+        let src = io::repeat(0).take(301_000_000);
+        let mut r = ThrottledReader::with_clock(src, 300_000_000, 1_000_000, clock.clone());
+
+        assert_eq!(io::copy(&mut r, &mut io::sink()).unwrap(), 301_000_000);
+        assert!(clock.elapsed() >= time::Duration::from_secs(1));
+        assert!(clock.elapsed() <= time::Duration::from_millis(1001));
+    }
+
+    #[test]
+    fn throttles_pipe() {
+        let (pipe_r, pipe_w) = io::pipe().unwrap();
+        let src = data(5000);
+        let before = time::Instant::now();
+
+        let expected = src.clone();
+        let writer = thread::spawn(move || {
+            let mut w = ThrottledWriter::new(pipe_w, 20_000, 1000);
+            w.write_all(&expected).unwrap();
+        });
+        let mut r = ThrottledReader::new(pipe_r, 20_000, 1000);
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        writer.join().unwrap();
+
+        assert_eq!(out, src);
+        assert!(time::Instant::now() >= before + time::Duration::from_millis(200));
+    }
+}
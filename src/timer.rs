@@ -0,0 +1,301 @@
+//! Timers for async waits.
+//!
+//! Deadlines are tracked by a single background thread holding a heap of
+//! pending wakeups, started the first time an async wait needs it. This
+//! keeps async waits independent of any particular executor, and enabling
+//! the `tokio` feature does not change it. With that feature, callers may
+//! also opt in to tokio's own timer, which then has to be polled within a
+//! tokio runtime that has its time driver enabled.
+
+use std::time;
+use std::task::{Context, Poll};
+
+/// A resettable wakeup, driven by the shared timer thread or, if asked for,
+/// by tokio's timer.
+pub enum Timer {
+    Thread(thread_timer::Timer),
+    #[cfg(feature = "tokio")]
+    Tokio(tokio_timer::Timer),
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer::Thread(thread_timer::Timer::new())
+    }
+
+    #[cfg(feature = "tokio")]
+    pub fn tokio() -> Timer {
+        Timer::Tokio(tokio_timer::Timer::new())
+    }
+
+    /// Complete once `at` has passed, arranging for the task to be woken
+    /// then if it has not.
+    pub fn poll_until(&mut self, at: time::Instant, cx: &mut Context) -> Poll<()> {
+        match *self {
+            Timer::Thread(ref mut timer) => timer.poll_until(at, cx),
+            #[cfg(feature = "tokio")]
+            Timer::Tokio(ref mut timer) => timer.poll_until(at, cx),
+        }
+    }
+
+    /// Complete once `remaining` has passed from now, as measured by the
+    /// real clock.
+    ///
+    /// Limiters may read time from a clock other than the real one, so a
+    /// wait they report is translated into a real deadline here.
+    pub fn poll_after(&mut self, remaining: time::Duration, cx: &mut Context) -> Poll<()> {
+        self.poll_until(time::Instant::now() + remaining, cx)
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub mod tokio_timer {
+    use std::time;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use tokio;
+
+    /// A resettable wakeup driven by tokio's timer.
+    pub struct Timer {
+        sleep: Option<Pin<Box<tokio::time::Sleep>>>,
+    }
+
+    impl Timer {
+        pub fn new() -> Timer {
+            Timer { sleep: None }
+        }
+
+        /// Complete once `at` has passed, arranging for the task to be woken
+        /// then if it has not.
+        pub fn poll_until(&mut self, at: time::Instant, cx: &mut Context) -> Poll<()> {
+            let at = tokio::time::Instant::from_std(at);
+            match self.sleep {
+                Some(ref mut sleep) if sleep.deadline() != at => sleep.as_mut().reset(at),
+                Some(_) => {}
+                None => self.sleep = Some(Box::pin(tokio::time::sleep_until(at))),
+            }
+            self.sleep.as_mut().unwrap().as_mut().poll(cx)
+        }
+    }
+}
+
+pub mod thread_timer {
+    use std::{thread, time};
+    use std::cmp::Reverse;
+    use std::collections::{BinaryHeap, HashMap};
+    use std::sync::{Condvar, Mutex, OnceLock};
+    use std::task::{Context, Poll, Waker};
+
+    struct Entry {
+        at: time::Instant,
+        waker: Waker,
+    }
+
+    #[derive(Default)]
+    struct Queue {
+        entries: HashMap<u64, Entry>,
+        // May hold stale deadlines for entries since moved or removed; these
+        // are skipped when they reach the top.
+        heap: BinaryHeap<Reverse<(time::Instant, u64)>>,
+        next_id: u64,
+    }
+
+    struct TimerThread {
+        queue: Mutex<Queue>,
+        cvar: Condvar,
+    }
+
+    fn global() -> &'static TimerThread {
+        static TIMER: OnceLock<TimerThread> = OnceLock::new();
+        TIMER.get_or_init(|| {
+            thread::Builder::new()
+                .name("fence-timer".into())
+                .spawn(|| global().run())
+                .expect("failed to spawn fence timer thread");
+            TimerThread {
+                queue: Mutex::new(Queue::default()),
+                cvar: Condvar::new(),
+            }
+        })
+    }
+
+    impl TimerThread {
+        fn run(&self) {
+            let mut queue = self.queue.lock().unwrap();
+            loop {
+                let now = time::Instant::now();
+                let mut due = Vec::new();
+                let mut next = None;
+                while let Some(&Reverse((at, id))) = queue.heap.peek() {
+                    let live = queue.entries.get(&id).is_some_and(|e| e.at == at);
+                    if live && at > now {
+                        next = Some(at);
+                        break;
+                    }
+                    queue.heap.pop();
+                    if live {
+                        due.push(queue.entries.remove(&id).unwrap().waker);
+                    }
+                }
+                if !due.is_empty() {
+                    drop(queue);
+                    for waker in due {
+                        waker.wake();
+                    }
+                    queue = self.queue.lock().unwrap();
+                    continue;
+                }
+                queue = match next {
+                    Some(at) => self.cvar.wait_timeout(queue, at - now).unwrap().0,
+                    None => self.cvar.wait(queue).unwrap(),
+                };
+            }
+        }
+
+        fn schedule(&self, id: Option<u64>, at: time::Instant, waker: &Waker) -> u64 {
+            let mut queue = self.queue.lock().unwrap();
+            let id = id.unwrap_or_else(|| {
+                queue.next_id += 1;
+                queue.next_id
+            });
+            let moved = match queue.entries.get_mut(&id) {
+                Some(entry) => {
+                    if !entry.waker.will_wake(waker) {
+                        entry.waker = waker.clone();
+                    }
+                    let moved = entry.at != at;
+                    entry.at = at;
+                    moved
+                }
+                None => {
+                    queue.entries.insert(id, Entry { at, waker: waker.clone() });
+                    true
+                }
+            };
+            if moved {
+                queue.heap.push(Reverse((at, id)));
+                self.cvar.notify_one();
+            }
+            id
+        }
+
+        fn cancel(&self, id: u64) {
+            self.queue.lock().unwrap().entries.remove(&id);
+        }
+    }
+
+    /// A resettable wakeup driven by the shared timer thread.
+    pub struct Timer {
+        id: Option<u64>,
+    }
+
+    impl Timer {
+        pub fn new() -> Timer {
+            Timer { id: None }
+        }
+
+        /// Complete once `at` has passed, arranging for the task to be woken
+        /// then if it has not.
+        pub fn poll_until(&mut self, at: time::Instant, cx: &mut Context) -> Poll<()> {
+            if time::Instant::now() >= at {
+                if let Some(id) = self.id.take() {
+                    global().cancel(id);
+                }
+                return Poll::Ready(());
+            }
+            self.id = Some(global().schedule(self.id, at, cx.waker()));
+            Poll::Pending
+        }
+    }
+
+    impl Drop for Timer {
+        fn drop(&mut self) {
+            if let Some(id) = self.id {
+                global().cancel(id);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::{thread, time};
+        use std::sync::{Arc, Mutex};
+        use std::task::{Context, Wake, Waker};
+        use super::Timer;
+
+        struct Record(usize, Arc<Mutex<Vec<usize>>>);
+
+        impl Wake for Record {
+            fn wake(self: Arc<Self>) {
+                self.1.lock().unwrap().push(self.0);
+            }
+        }
+
+        #[test]
+        fn wakes_in_deadline_order() {
+            let woken = Arc::new(Mutex::new(Vec::new()));
+            let start = time::Instant::now();
+            let mut timers: Vec<_> = (0..4).map(|_| Timer::new()).collect();
+            for (i, ms) in [40u64, 10, 30, 20].iter().enumerate() {
+                let waker = Waker::from(Arc::new(Record(i, woken.clone())));
+                let at = start + time::Duration::from_millis(*ms);
+                assert!(timers[i].poll_until(at, &mut Context::from_waker(&waker)).is_pending());
+            }
+            thread::sleep(time::Duration::from_millis(200));
+            assert_eq!(*woken.lock().unwrap(), vec![1, 3, 2, 0]);
+        }
+
+        #[test]
+        fn dropped_timer_does_not_wake() {
+            let woken = Arc::new(Mutex::new(Vec::new()));
+            let waker = Waker::from(Arc::new(Record(0, woken.clone())));
+            let at = time::Instant::now() + time::Duration::from_millis(10);
+            {
+                let mut timer = Timer::new();
+                assert!(timer.poll_until(at, &mut Context::from_waker(&waker)).is_pending());
+            }
+            thread::sleep(time::Duration::from_millis(50));
+            assert!(woken.lock().unwrap().is_empty());
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use std::time;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use super::Timer;
+    use testing::block_on;
+
+    struct Wait(Timer, time::Instant);
+
+    impl Future for Wait {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            let at = self.1;
+            self.0.poll_until(at, cx)
+        }
+    }
+
+    #[test]
+    fn tokio_timer_within_runtime() {
+        let at = time::Instant::now() + time::Duration::from_millis(20);
+        block_on(Wait(Timer::tokio(), at));
+        assert!(time::Instant::now() >= at);
+    }
+
+    #[test]
+    fn thread_timer_needs_no_time_driver() {
+        use tokio;
+
+        let at = time::Instant::now() + time::Duration::from_millis(20);
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        rt.block_on(Wait(Timer::new(), at));
+        assert!(time::Instant::now() >= at);
+    }
+}
@@ -0,0 +1,573 @@
+use std::time;
+use std::collections::VecDeque;
+
+use clock::{Clock, MonotonicClock};
+use limiter::RateLimiter;
+
+/// A limiter admitting at most `limit` events in any `window`-long span.
+///
+/// The log keeps the instant of every admitted event still inside the
+/// window, so the guarantee is exact at the cost of memory proportional to
+/// `limit`.
+pub struct SlidingWindowLog<C = MonotonicClock> {
+    clock: C,
+    limit: u32,
+    window: time::Duration,
+    log: VecDeque<time::Instant>,
+}
+
+impl SlidingWindowLog {
+
+    /// Construct a limiter admitting `limit` events per sliding `window`. A
+    /// `limit` of zero is treated as one.
+    pub fn new(limit: u32, window: time::Duration) -> SlidingWindowLog {
+        SlidingWindowLog::with_clock(limit, window, MonotonicClock)
+    }
+}
+
+impl<C: Clock> SlidingWindowLog<C> {
+
+    /// Construct a limiter as with `new`, reading time from `clock`.
+    pub fn with_clock(limit: u32, window: time::Duration, clock: C) -> SlidingWindowLog<C> {
+        let limit = limit.max(1);
+        SlidingWindowLog {
+            clock,
+            limit,
+            window,
+            log: VecDeque::new(),
+        }
+    }
+
+    /// Admit an event if the window has room, without blocking.
+    ///
+    /// On denial, returns the instant at which the oldest logged event
+    /// leaves the window.
+    pub fn check(&mut self) -> Result<(), time::Instant> {
+        let now = self.clock.now();
+        while let Some(&oldest) = self.log.front() {
+            if oldest + self.window > now {
+                break;
+            }
+            self.log.pop_front();
+        }
+        match self.ready_at(now) {
+            None => {
+                self.log.push_back(now);
+                Ok(())
+            }
+            Some(ready) => Err(ready),
+        }
+    }
+
+    /// Return true and admit an event if the window has room.
+    pub fn allow(&mut self) -> bool {
+        self.check().is_ok()
+    }
+
+    /// Sleep the current thread until the window has room, then admit an
+    /// event.
+    pub fn sleep(&mut self) {
+        while let Err(ready) = self.check() {
+            let now = self.clock.now();
+            self.clock.sleep(ready.saturating_duration_since(now));
+        }
+    }
+
+    /// The instant at which the window will next have room, or `None` if
+    /// it has room at `now`.
+    fn ready_at(&self, now: time::Instant) -> Option<time::Instant> {
+        let window = self.window;
+        let live = self.log.iter().skip_while(|&&t| t + window <= now);
+        let count = live.clone().count();
+        if count < self.limit as usize {
+            return None;
+        }
+        let blocking = live.clone().nth(count - self.limit as usize);
+        Some(blocking.map_or(now + window, |&t| t + window))
+    }
+}
+
+impl<C: Clock> RateLimiter for SlidingWindowLog<C> {
+    fn try_acquire(&mut self) -> bool {
+        self.allow()
+    }
+
+    fn acquire(&mut self) {
+        self.sleep()
+    }
+
+    fn time_until_ready(&self) -> time::Duration {
+        let now = self.clock.now();
+        self.ready_at(now).map_or(time::Duration::from_secs(0), |t| t - now)
+    }
+}
+
+/// An approximate sliding window limiter using two counters.
+///
+/// Events are counted in fixed windows, and the count for the sliding
+/// window ending now is estimated by weighting the previous window's count
+/// by how much of it still overlaps. Memory use is constant regardless of
+/// `limit`, in exchange for assuming events were spread evenly across the
+/// previous window. Since the previous window's events may in fact have
+/// bunched at its end, up to twice `limit` events can pass in a
+/// `window`-long span straddling a boundary, though never more than `limit`
+/// in a single fixed window.
+pub struct SlidingWindowCounter<C = MonotonicClock> {
+    clock: C,
+    limit: u32,
+    window: time::Duration,
+    start: time::Instant,
+    previous: u32,
+    current: u32,
+}
+
+impl SlidingWindowCounter {
+
+    /// Construct a limiter admitting roughly `limit` events per sliding
+    /// `window`. A `limit` of zero is treated as one.
+    pub fn new(limit: u32, window: time::Duration) -> SlidingWindowCounter {
+        SlidingWindowCounter::with_clock(limit, window, MonotonicClock)
+    }
+}
+
+impl<C: Clock> SlidingWindowCounter<C> {
+
+    /// Construct a limiter as with `new`, reading time from `clock`.
+    pub fn with_clock(limit: u32, window: time::Duration, clock: C) -> SlidingWindowCounter<C> {
+        let start = clock.now();
+        SlidingWindowCounter {
+            clock,
+            limit: limit.max(1),
+            window,
+            start,
+            previous: 0,
+            current: 0,
+        }
+    }
+
+    /// Admit an event if the estimated count is under the limit, without
+    /// blocking.
+    ///
+    /// On denial, returns the earliest instant at which the estimate will
+    /// have decayed enough to admit an event.
+    pub fn check(&mut self) -> Result<(), time::Instant> {
+        let now = self.clock.now();
+        self.roll(now);
+        match self.ready_at(now) {
+            None => {
+                self.current += 1;
+                Ok(())
+            }
+            Some(ready) => Err(ready),
+        }
+    }
+
+    /// Return true and admit an event if the estimated count is under the
+    /// limit.
+    pub fn allow(&mut self) -> bool {
+        self.check().is_ok()
+    }
+
+    /// Sleep the current thread until the estimated count is under the
+    /// limit, then admit an event.
+    pub fn sleep(&mut self) {
+        while let Err(ready) = self.check() {
+            let now = self.clock.now();
+            self.clock.sleep(ready.saturating_duration_since(now));
+        }
+    }
+
+    /// The instant at which the estimate will next admit an event, or
+    /// `None` if it admits one at `now`.
+    fn ready_at(&self, now: time::Instant) -> Option<time::Instant> {
+        let (start, previous, current) = self.rolled(now);
+        let end = start + self.window;
+        if current < self.limit {
+            let remaining = end.saturating_duration_since(now);
+            let delay = decay_delay(previous, self.limit - current, remaining, self.window);
+            if delay.as_nanos() == 0 {
+                return None;
+            }
+            return Some(now + delay);
+        }
+        // The current window is full, so the earliest chance is in the next
+        // one, where today's count becomes the weighted previous count.
+        Some(end + decay_delay(current, self.limit, self.window, self.window))
+    }
+
+    /// The window start and counts as they will be at `now`.
+    fn rolled(&self, now: time::Instant) -> (time::Instant, u32, u32) {
+        let window = self.window.as_nanos();
+        if window == 0 {
+            return (now, 0, 0);
+        }
+        let elapsed = now.saturating_duration_since(self.start).as_nanos() / window;
+        match elapsed {
+            0 => (self.start, self.previous, self.current),
+            1 => (self.start + self.window, self.current, 0),
+            _ => (self.start + time::Duration::from_nanos((elapsed * window) as u64), 0, 0),
+        }
+    }
+
+    fn roll(&mut self, now: time::Instant) {
+        let (start, previous, current) = self.rolled(now);
+        self.start = start;
+        self.previous = previous;
+        self.current = current;
+    }
+}
+
+/// How long until `previous` events, weighted by the `remaining` fraction of
+/// `window`, fall below `headroom`.
+fn decay_delay(previous: u32, headroom: u32, remaining: time::Duration,
+               window: time::Duration) -> time::Duration {
+    if previous == 0 {
+        return time::Duration::from_secs(0);
+    }
+    if headroom == 0 {
+        return remaining;
+    }
+    let window = window.as_nanos();
+    let remaining = remaining.as_nanos();
+    // Admit while previous * remaining / window < headroom.
+    let admissible = (headroom as u128 * window).saturating_sub(1) / previous as u128;
+    time::Duration::from_nanos(remaining.saturating_sub(admissible) as u64)
+}
+
+impl<C: Clock> RateLimiter for SlidingWindowCounter<C> {
+    fn try_acquire(&mut self) -> bool {
+        self.allow()
+    }
+
+    fn acquire(&mut self) {
+        self.sleep()
+    }
+
+    fn time_until_ready(&self) -> time::Duration {
+        let now = self.clock.now();
+        self.ready_at(now).map_or(time::Duration::from_secs(0), |t| t - now)
+    }
+}
+
+/// A limiter admitting at most `limit` events per fixed window.
+///
+/// The count resets all at once when a window ends. Windows start either
+/// when the limiter is constructed or, with `aligned`, on wall-clock
+/// multiples of the window length, matching quotas that reset on the
+/// minute or the hour. Up to twice `limit` events may pass in a short span
+/// straddling a boundary.
+pub struct FixedWindow<C = MonotonicClock> {
+    clock: C,
+    limit: u32,
+    window: time::Duration,
+    start: time::Instant,
+    count: u32,
+}
+
+impl FixedWindow {
+
+    /// Construct a limiter admitting `limit` events per `window`, with the
+    /// first window starting now. A `limit` of zero is treated as one.
+    pub fn new(limit: u32, window: time::Duration) -> FixedWindow {
+        FixedWindow::with_clock(limit, window, MonotonicClock)
+    }
+
+    /// Construct a limiter admitting `limit` events per `window`, with
+    /// windows aligned to whole multiples of `window` since the Unix epoch.
+    /// A `limit` of zero is treated as one.
+    pub fn aligned(limit: u32, window: time::Duration) -> FixedWindow {
+        FixedWindow::aligned_with_clock(limit, window, MonotonicClock)
+    }
+}
+
+impl<C: Clock> FixedWindow<C> {
+
+    /// Construct a limiter as with `new`, reading time from `clock`.
+    pub fn with_clock(limit: u32, window: time::Duration, clock: C) -> FixedWindow<C> {
+        let start = clock.now();
+        FixedWindow {
+            clock,
+            limit: limit.max(1),
+            window,
+            start,
+            count: 0,
+        }
+    }
+
+    /// Construct a limiter as with `aligned`, reading time from `clock`.
+    pub fn aligned_with_clock(limit: u32, window: time::Duration, clock: C) -> FixedWindow<C> {
+        let now = clock.now();
+        let since_epoch = clock.system_time()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let into_window = match window.as_nanos() {
+            0 => 0,
+            w => since_epoch % w,
+        };
+        let offset = time::Duration::from_nanos(into_window as u64);
+        let mut fw = FixedWindow::with_clock(limit, window, clock);
+        fw.start = now.checked_sub(offset).unwrap_or(now);
+        fw
+    }
+
+    /// Admit an event if the current window has room, without blocking.
+    ///
+    /// On denial, returns the instant at which the current window ends.
+    pub fn check(&mut self) -> Result<(), time::Instant> {
+        let now = self.clock.now();
+        self.roll(now);
+        match self.ready_at(now) {
+            None => {
+                self.count += 1;
+                Ok(())
+            }
+            Some(ready) => Err(ready),
+        }
+    }
+
+    /// Return true and admit an event if the current window has room.
+    pub fn allow(&mut self) -> bool {
+        self.check().is_ok()
+    }
+
+    /// Sleep the current thread until the current window has room, then
+    /// admit an event.
+    pub fn sleep(&mut self) {
+        while let Err(ready) = self.check() {
+            let now = self.clock.now();
+            self.clock.sleep(ready.saturating_duration_since(now));
+        }
+    }
+
+    /// The instant at which the window will next have room, or `None` if
+    /// it has room at `now`.
+    fn ready_at(&self, now: time::Instant) -> Option<time::Instant> {
+        let (start, count) = self.rolled(now);
+        if count < self.limit {
+            return None;
+        }
+        Some(start + self.window)
+    }
+
+    /// The window start and count as they will be at `now`.
+    fn rolled(&self, now: time::Instant) -> (time::Instant, u32) {
+        let window = self.window.as_nanos();
+        if window == 0 {
+            return (now, 0);
+        }
+        let elapsed = now.saturating_duration_since(self.start).as_nanos() / window;
+        if elapsed == 0 {
+            return (self.start, self.count);
+        }
+        (self.start + time::Duration::from_nanos((elapsed * window) as u64), 0)
+    }
+
+    fn roll(&mut self, now: time::Instant) {
+        let (start, count) = self.rolled(now);
+        self.start = start;
+        self.count = count;
+    }
+}
+
+impl<C: Clock> RateLimiter for FixedWindow<C> {
+    fn try_acquire(&mut self) -> bool {
+        self.allow()
+    }
+
+    fn acquire(&mut self) {
+        self.sleep()
+    }
+
+    fn time_until_ready(&self) -> time::Duration {
+        let now = self.clock.now();
+        self.ready_at(now).map_or(time::Duration::from_secs(0), |t| t - now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time;
+    use {Clock, FixedWindow, MockClock, SlidingWindowCounter, SlidingWindowLog};
+
+    // A small deterministic generator for bursty arrival gaps.
+    fn gaps(n: usize) -> Vec<time::Duration> {
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        (0..n).map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            match (state >> 33) % 10 {
+                0 => time::Duration::from_secs(7),
+                1 | 2 => time::Duration::from_millis(900),
+                _ => time::Duration::from_millis(3),
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn log_never_exceeds_limit_in_any_window() {
+        let clock = MockClock::new();
+        let window = time::Duration::from_secs(10);
+        let mut l = SlidingWindowLog::with_clock(5, window, clock.clone());
+
+        let mut admitted = Vec::new();
+        for gap in gaps(2000) {
+            clock.advance(gap);
+            if l.allow() {
+                admitted.push(clock.now());
+            }
+        }
+        assert!(admitted.len() > 5);
+        for (i, &t) in admitted.iter().enumerate() {
+            let in_window = admitted[i..].iter().take_while(|&&u| u < t + window).count();
+            assert!(in_window <= 5);
+        }
+    }
+
+    #[test]
+    fn log_huge_limit_allocates_lazily() {
+        let mut l = SlidingWindowLog::with_clock(u32::MAX, time::Duration::from_secs(60), MockClock::new());
+        for _ in 0..100 {
+            assert!(l.allow());
+        }
+    }
+
+    #[test]
+    fn log_sleep_waits_for_oldest_to_expire() {
+        let clock = MockClock::new();
+        let mut l = SlidingWindowLog::with_clock(3, time::Duration::from_secs(10), clock.clone());
+
+        for _ in 0..3 {
+            l.sleep();
+        }
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(0));
+        l.sleep();
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(10));
+    }
+
+    #[test]
+    fn counter_stays_within_estimate() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let window = time::Duration::from_secs(10);
+        let mut c = SlidingWindowCounter::with_clock(5, window, clock.clone());
+
+        let mut admitted = Vec::new();
+        for gap in gaps(2000) {
+            clock.advance(gap);
+            if c.allow() {
+                admitted.push(clock.now() - start);
+            }
+        }
+        assert!(admitted.len() > 5);
+
+        // Recompute the estimate from the log: each admission must have
+        // seen the weighted previous count plus the current count below the
+        // limit.
+        let w = window.as_nanos();
+        let index = |t: time::Duration| t.as_nanos() / w;
+        let mut straddling = false;
+        for (i, &t) in admitted.iter().enumerate() {
+            let k = index(t);
+            let current = admitted[..i].iter().filter(|&&u| index(u) == k).count() as u128;
+            let previous = admitted[..i].iter().filter(|&&u| index(u) + 1 == k).count() as u128;
+            let remaining = (k + 1) * w - t.as_nanos();
+            assert!(current < 5);
+            assert!(previous * remaining < (5 - current) * w);
+
+            let in_window = admitted[i..].iter().take_while(|&&u| u < t + window).count();
+            assert!(in_window <= 10);
+            straddling |= in_window > 5;
+        }
+        // The bursty traffic does push a straddling span past the limit,
+        // which the estimate permits.
+        assert!(straddling);
+    }
+
+    #[test]
+    fn zero_limit_admits_one() {
+        let clock = MockClock::new();
+        let window = time::Duration::from_secs(10);
+        let mut l = SlidingWindowLog::with_clock(0, window, clock.clone());
+        let mut c = SlidingWindowCounter::with_clock(0, window, clock.clone());
+
+        l.sleep();
+        c.sleep();
+        assert!(!l.allow());
+        assert!(!c.allow());
+        l.sleep();
+        assert_eq!(clock.elapsed(), window);
+    }
+
+    #[test]
+    fn counter_weights_previous_window() {
+        let clock = MockClock::new();
+        let mut c = SlidingWindowCounter::with_clock(4, time::Duration::from_secs(10), clock.clone());
+
+        for _ in 0..4 {
+            assert!(c.allow());
+        }
+        assert!(!c.allow());
+
+        // A quarter into the next window, three quarters of the previous
+        // count still applies: 4 * 0.75 = 3, leaving room for one event.
+        clock.advance(time::Duration::from_millis(12500));
+        assert!(c.allow());
+        assert!(!c.allow());
+    }
+
+    #[test]
+    fn counter_sleep_matches_steady_rate() {
+        let clock = MockClock::new();
+        let mut c = SlidingWindowCounter::with_clock(4, time::Duration::from_secs(10), clock.clone());
+
+        for _ in 0..44 {
+            c.sleep();
+        }
+        let elapsed = clock.elapsed();
+        assert!(elapsed >= time::Duration::from_secs(90));
+        assert!(elapsed <= time::Duration::from_secs(110));
+    }
+
+    #[test]
+    fn fixed_window_resets_at_boundary() {
+        let clock = MockClock::new();
+        let mut w = FixedWindow::with_clock(3, time::Duration::from_secs(60), clock.clone());
+
+        for _ in 0..3 {
+            assert!(w.allow());
+        }
+        assert!(!w.allow());
+        clock.advance(time::Duration::from_secs(59));
+        assert!(!w.allow());
+        clock.advance(time::Duration::from_secs(1));
+        for _ in 0..3 {
+            assert!(w.allow());
+        }
+        assert!(!w.allow());
+    }
+
+    #[test]
+    fn fixed_window_zero_limit_admits_one() {
+        let clock = MockClock::new();
+        let mut w = FixedWindow::with_clock(0, time::Duration::from_secs(60), clock.clone());
+
+        w.sleep();
+        assert!(!w.allow());
+        w.sleep();
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(60));
+    }
+
+    #[test]
+    fn fixed_window_aligns_to_wall_clock() {
+        let clock = MockClock::new();
+        clock.advance(time::Duration::from_secs(45));
+        let mut w = FixedWindow::aligned_with_clock(2, time::Duration::from_secs(60), clock.clone());
+
+        assert!(w.allow());
+        assert!(w.allow());
+        assert_eq!(w.check(), Err(clock.now() + time::Duration::from_secs(15)));
+
+        w.sleep();
+        assert_eq!(clock.elapsed(), time::Duration::from_secs(60));
+    }
+}